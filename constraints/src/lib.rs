@@ -0,0 +1,284 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Outcome of assigning every key a distinct value from its candidates.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Solution<K, V> {
+    Unique(BTreeMap<K, V>),
+    /// At least two consistent assignments, up to the requested limit.
+    Ambiguous(Vec<BTreeMap<K, V>>),
+    /// No consistent assignment, naming the key that ran out of candidates if there is one.
+    Impossible(Option<K>),
+}
+
+/// A value fixed for a key by propagation and the other keys it was eliminated from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deduction<K, V> {
+    pub key: K,
+    pub value: V,
+    pub eliminated_from: Vec<K>,
+}
+
+/// Keys that each need a distinct value, e.g. ticket positions to rules or allergens to ingredients.
+#[derive(Debug, Clone, Default)]
+pub struct Problem<K, V> {
+    candidates: BTreeMap<K, BTreeSet<V>>,
+}
+
+impl<K: Ord + Clone, V: Ord + Clone> Problem<K, V> {
+    pub fn new() -> Self {
+        Problem{candidates: BTreeMap::new()}
+    }
+
+    /// Narrows the candidates of a key to those also in `allowed`, adding the key if it is new.
+    pub fn restrict<I: IntoIterator<Item=V>>(&mut self, key: K, allowed: I) {
+        let allowed: BTreeSet<V> = allowed.into_iter().collect();
+        match self.candidates.get_mut(&key) {
+            Some(current) => current.retain(|v| allowed.contains(v)),
+            None => { self.candidates.insert(key, allowed); },
+        }
+    }
+
+    pub fn remove(&mut self, key: &K, value: &V) -> bool {
+        match self.candidates.get_mut(key) {
+            Some(current) => current.remove(value),
+            None => false,
+        }
+    }
+
+    pub fn candidates(&self, key: &K) -> Option<&BTreeSet<V>> {
+        self.candidates.get(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item=&K> {
+        self.candidates.keys()
+    }
+
+    pub fn determined(&self, key: &K) -> Option<&V> {
+        match self.candidates.get(key) {
+            Some(current) if current.len() == 1 => current.iter().next(),
+            _ => None,
+        }
+    }
+
+    pub fn is_determined(&self) -> bool {
+        self.candidates.values().all(|c| c.len() == 1)
+    }
+
+    /// Removes every determined value from the other keys until nothing changes, returning what was deduced.
+    /// Fails with the first key left without candidates.
+    pub fn propagate(&mut self) -> Result<Vec<Deduction<K, V>>, K> {
+        let mut deductions: Vec<Deduction<K, V>> = vec![];
+        let mut purged: BTreeSet<K> = BTreeSet::new();
+        loop {
+            if let Some((key, _)) = self.candidates.iter().find(|(_, c)| c.is_empty()) {
+                return Err(key.clone());
+            }
+            let next = self.candidates
+                .iter()
+                .find(|(k, c)| c.len() == 1 && !purged.contains(*k))
+                .map(|(k, c)| (k.clone(), c.iter().next().unwrap().clone()));
+            match next {
+                Some((key, value)) => {
+                    let mut eliminated_from: Vec<K> = vec![];
+                    for (other, current) in self.candidates.iter_mut() {
+                        if *other != key && current.remove(&value) {
+                            eliminated_from.push(other.clone());
+                        }
+                    }
+                    purged.insert(key.clone());
+                    deductions.push(Deduction{key, value, eliminated_from});
+                },
+                None => return Ok(deductions),
+            }
+        }
+    }
+
+    /// Propagates and then searches for assignments, collecting at most `limit` (and at least two).
+    /// The search only enters branches where the remaining keys still have a matching, so it never
+    /// backtracks out of a dead end.
+    pub fn solve(&self, limit: usize) -> Solution<K, V> {
+        let mut problem = self.clone();
+        if let Err(key) = problem.propagate() {
+            return Solution::Impossible(Some(key));
+        }
+        let mut keys: Vec<&K> = problem.candidates.keys().collect();
+        keys.sort_by_key(|k| problem.candidates[*k].len());
+        let mut found: Vec<BTreeMap<K, V>> = vec![];
+        let mut assignment: BTreeMap<K, V> = BTreeMap::new();
+        let mut used: BTreeSet<V> = BTreeSet::new();
+        if !problem.has_matching(&keys, &used) {
+            return Solution::Impossible(None);
+        }
+        problem.search(&keys, &mut assignment, &mut used, &mut found, limit.max(2));
+        match found.len() {
+            0 => Solution::Impossible(None),
+            1 => Solution::Unique(found.pop().unwrap()),
+            _ => Solution::Ambiguous(found),
+        }
+    }
+
+    fn search(
+        &self,
+        keys: &[&K],
+        assignment: &mut BTreeMap<K, V>,
+        used: &mut BTreeSet<V>,
+        found: &mut Vec<BTreeMap<K, V>>,
+        limit: usize,
+    ) {
+        let key = match keys.first() {
+            Some(key) => *key,
+            None => {
+                found.push(assignment.clone());
+                return;
+            },
+        };
+        for value in self.candidates[key].iter() {
+            if used.contains(value) { continue; }
+            used.insert(value.clone());
+            if self.has_matching(&keys[1..], used) {
+                assignment.insert(key.clone(), value.clone());
+                self.search(&keys[1..], assignment, used, found, limit);
+                assignment.remove(key);
+            }
+            used.remove(value);
+            if found.len() >= limit { return; }
+        }
+    }
+
+    /// Whether every key can take a distinct candidate outside `used`, grown one key at a time
+    /// along augmenting paths.
+    fn has_matching(&self, keys: &[&K], used: &BTreeSet<V>) -> bool {
+        let mut owner: BTreeMap<&V, usize> = BTreeMap::new();
+        for idx in 0..keys.len() {
+            let mut visited: BTreeSet<&V> = BTreeSet::new();
+            if !self.augment(keys, idx, used, &mut owner, &mut visited) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn augment<'a>(
+        &'a self,
+        keys: &[&K],
+        idx: usize,
+        used: &BTreeSet<V>,
+        owner: &mut BTreeMap<&'a V, usize>,
+        visited: &mut BTreeSet<&'a V>,
+    ) -> bool {
+        for value in self.candidates[keys[idx]].iter() {
+            if used.contains(value) || !visited.insert(value) { continue; }
+            let free = match owner.get(value) {
+                Some(other) => {
+                    let other = *other;
+                    self.augment(keys, other, used, owner, visited)
+                },
+                None => true,
+            };
+            if free {
+                owner.insert(value, idx);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(candidates: &[(&'static str, &[u32])]) -> Problem<&'static str, u32> {
+        let mut problem = Problem::new();
+        for (key, values) in candidates {
+            problem.restrict(*key, values.iter().cloned());
+        }
+        problem
+    }
+
+    #[test]
+    fn unique_by_propagation() {
+        let p = problem(&[("a", &[1]), ("b", &[1, 2]), ("c", &[1, 2, 3])]);
+        let expected: BTreeMap<&str, u32> = [("a", 1), ("b", 2), ("c", 3)].iter().cloned().collect();
+        assert_eq!(p.solve(10), Solution::Unique(expected));
+    }
+
+    #[test]
+    fn ambiguous_respects_limit() {
+        let p = problem(&[("a", &[1, 2, 3, 4]), ("b", &[1, 2, 3, 4]), ("c", &[1, 2, 3, 4]), ("d", &[1, 2, 3, 4])]);
+        match p.solve(5) {
+            Solution::Ambiguous(found) => {
+                assert_eq!(found.len(), 5);
+                for assignment in found.iter() {
+                    assert_eq!(assignment.values().collect::<BTreeSet<_>>().len(), 4);
+                }
+            },
+            other => panic!("Expected ambiguous, got {:?}", other),
+        }
+        match p.solve(100) {
+            Solution::Ambiguous(found) => assert_eq!(found.len(), 24),
+            other => panic!("Expected ambiguous, got {:?}", other),
+        }
+        // At least two are collected so ambiguity is always reported
+        match p.solve(1) {
+            Solution::Ambiguous(found) => assert_eq!(found.len(), 2),
+            other => panic!("Expected ambiguous, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn impossible_names_the_emptied_key() {
+        let p = problem(&[("a", &[1]), ("b", &[1]), ("c", &[2, 3])]);
+        assert_eq!(p.solve(10), Solution::Impossible(Some("b")));
+        let p = problem(&[("a", &[1]), ("b", &[])]);
+        assert_eq!(p.solve(10), Solution::Impossible(Some("b")));
+    }
+
+    #[test]
+    fn impossible_without_an_emptied_key() {
+        // Three keys share two values, which propagation alone cannot see
+        let p = problem(&[("a", &[1, 2]), ("b", &[1, 2]), ("c", &[1, 2])]);
+        assert_eq!(p.solve(10), Solution::Impossible(None));
+    }
+
+    #[test]
+    fn pigeonhole_fails_without_searching() {
+        let values: Vec<u32> = (0..15).collect();
+        let mut p: Problem<u32, u32> = Problem::new();
+        for key in 0..16 {
+            p.restrict(key, values.iter().cloned());
+        }
+        assert_eq!(p.solve(10), Solution::Impossible(None));
+    }
+
+    #[test]
+    fn buried_conflict_among_many_keys() {
+        // The first `crowded` keys share two values while the rest could take anything
+        let crowded = |count: u32| {
+            let mut p: Problem<u32, u32> = Problem::new();
+            for key in 0..20 {
+                match key < count {
+                    true => p.restrict(key, 0..2),
+                    false => p.restrict(key, 0..20),
+                }
+            }
+            p
+        };
+        assert_eq!(crowded(3).solve(10), Solution::Impossible(None));
+        match crowded(2).solve(3) {
+            Solution::Ambiguous(found) => assert_eq!(found.len(), 3),
+            other => panic!("Expected ambiguous, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn propagate_reports_deductions() {
+        let mut p = problem(&[("a", &[1]), ("b", &[1, 2]), ("c", &[2, 3])]);
+        let deductions = p.propagate().unwrap();
+        assert_eq!(deductions.len(), 3);
+        assert_eq!(deductions[0], Deduction{key: "a", value: 1, eliminated_from: vec!["b"]});
+        assert_eq!(deductions[1], Deduction{key: "b", value: 2, eliminated_from: vec!["c"]});
+        assert!(p.is_determined());
+        assert_eq!(p.determined(&"c"), Some(&3));
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet};
+
+// One way of picking k entries summing to the target, indices ascending
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution {
+    pub indices: Vec<usize>,
+    pub values: Vec<i32>,
+}
+
+impl Solution {
+    pub(crate) fn from_indices(values: &[i32], mut indices: Vec<usize>) -> Solution {
+        indices.sort_unstable();
+        let picked = indices.iter().map(|i| values[*i]).collect();
+        Solution{indices, values: picked}
+    }
+
+    // Solutions are distinct when they use a different multiset of values
+    fn key(&self) -> Vec<i32> {
+        let mut key = self.values.clone();
+        key.sort_unstable();
+        key
+    }
+
+    // None once the product no longer fits in an i64
+    pub fn product(&self) -> Option<i64> {
+        self.values.iter().try_fold(1i64, |acc, v| acc.checked_mul(*v as i64))
+    }
+}
+
+// Picks between algorithms by k: hashing for pairs, sort and two pointers for
+// small k, and meeting in the middle once the two-pointer recursion gets too deep
+pub fn k_sum(values: &[i32], target: i64, k: usize) -> Vec<Solution> {
+    let mut solutions = match k {
+        _ if k > values.len() => Vec::new(),
+        0 => match target { 0 => vec![Solution{indices: Vec::new(), values: Vec::new()}], _ => Vec::new() },
+        1 => single(values, target),
+        2 => hashed_pair(values, target),
+        3..=4 => two_pointer(values, target, k),
+        _ => meet_in_the_middle(values, target, k),
+    };
+    solutions.sort_by(|a, b| a.indices.cmp(&b.indices));
+    solutions
+}
+
+fn single(values: &[i32], target: i64) -> Vec<Solution> {
+    match values.iter().position(|v| *v as i64 == target) {
+        Some(idx) => vec![Solution::from_indices(values, vec![idx])],
+        None => Vec::new(),
+    }
+}
+
+fn hashed_pair(values: &[i32], target: i64) -> Vec<Solution> {
+    let mut first_seen: HashMap<i64, usize> = HashMap::new();
+    let mut found: HashSet<(i64, i64)> = HashSet::new();
+    let mut solutions: Vec<Solution> = Vec::new();
+    for (idx, value) in values.iter().enumerate() {
+        let value = *value as i64;
+        let complement = target - value;
+        if let Some(other) = first_seen.get(&complement) {
+            if found.insert((value.min(complement), value.max(complement))) {
+                solutions.push(Solution::from_indices(values, vec![*other, idx]));
+            }
+        }
+        first_seen.entry(value).or_insert(idx);
+    }
+    solutions
+}
+
+fn two_pointer(values: &[i32], target: i64, k: usize) -> Vec<Solution> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by_key(|i| values[*i]);
+    let sorted: Vec<i64> = order.iter().map(|i| values[*i] as i64).collect();
+    let mut solutions: Vec<Solution> = Vec::new();
+    let mut chosen: Vec<usize> = Vec::with_capacity(k);
+    sorted_k_sum(&sorted, 0, k, target, &mut chosen, &mut |picked| {
+        let indices = picked.iter().map(|p| order[*p]).collect();
+        solutions.push(Solution::from_indices(values, indices));
+    });
+    solutions
+}
+
+// Walks positions of the sorted values, skipping equal neighbours so every
+// multiset of values is reported once
+fn sorted_k_sum<F>(sorted: &[i64], start: usize, k: usize, target: i64, chosen: &mut Vec<usize>, found: &mut F)
+where F: FnMut(&[usize]) {
+    let n = sorted.len();
+    if k == 2 {
+        let (mut lo, mut hi) = (start, n - 1);
+        while lo < hi {
+            let sum = sorted[lo] + sorted[hi];
+            if sum == target {
+                chosen.extend([lo, hi].iter());
+                found(chosen);
+                chosen.truncate(chosen.len() - 2);
+                while lo < hi && sorted[lo] == sorted[lo + 1] {
+                    lo += 1;
+                }
+                lo += 1;
+                hi -= 1;
+            } else if sum < target {
+                lo += 1;
+            } else {
+                hi -= 1;
+            }
+        }
+        return;
+    }
+    for idx in start..=n - k {
+        if idx > start && sorted[idx] == sorted[idx - 1] {
+            continue;
+        }
+        let smallest: i64 = sorted[idx..idx + k].iter().sum();
+        if smallest > target {
+            break;
+        }
+        let largest: i64 = sorted[idx] + sorted[n + 1 - k..].iter().sum::<i64>();
+        if largest < target {
+            continue;
+        }
+        chosen.push(idx);
+        sorted_k_sum(sorted, idx + 1, k - 1, target - sorted[idx], chosen, found);
+        chosen.pop();
+    }
+}
+
+// Every index set splits uniquely into its lowest `k / 2` indices and the rest, so
+// halves are only joined when the low half ends before the high half starts
+fn meet_in_the_middle(values: &[i32], target: i64, k: usize) -> Vec<Solution> {
+    let low_size = k / 2;
+    let mut low_halves: HashMap<i64, Vec<Vec<usize>>> = HashMap::new();
+    for_each_combination(values.len(), low_size, &mut |combination| {
+        let sum: i64 = combination.iter().map(|i| values[*i] as i64).sum();
+        low_halves.entry(sum).or_default().push(combination.to_vec());
+    });
+    let mut found: HashSet<Vec<i32>> = HashSet::new();
+    let mut solutions: Vec<Solution> = Vec::new();
+    for_each_combination(values.len(), k - low_size, &mut |high| {
+        let sum: i64 = high.iter().map(|i| values[*i] as i64).sum();
+        if let Some(lows) = low_halves.get(&(target - sum)) {
+            for low in lows.iter().filter(|low| low.last() < high.first()) {
+                let indices: Vec<usize> = low.iter().chain(high.iter()).cloned().collect();
+                let solution = Solution::from_indices(values, indices);
+                if found.insert(solution.key()) {
+                    solutions.push(solution);
+                }
+            }
+        }
+    });
+    solutions
+}
+
+fn for_each_combination<F>(n: usize, r: usize, f: &mut F)
+where F: FnMut(&[usize]) {
+    if r > n {
+        return;
+    }
+    let mut combination: Vec<usize> = (0..r).collect();
+    loop {
+        f(&combination);
+        let mut pos = r;
+        while pos > 0 && combination[pos - 1] == n - r + pos - 1 {
+            pos -= 1;
+        }
+        if pos == 0 {
+            return;
+        }
+        combination[pos - 1] += 1;
+        for next in pos..r {
+            combination[next] = combination[next - 1] + 1;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    // Small pseudo-random lists with plenty of duplicates and negatives
+    pub(crate) fn random_lists(count: usize) -> Vec<(Vec<i32>, i64)> {
+        let mut state: u64 = 0x2020;
+        let mut next = |bound: u64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) % bound
+        };
+        (0..count)
+            .map(|_| {
+                let len = next(11) as usize;
+                let values = (0..len).map(|_| next(31) as i32 - 15).collect();
+                (values, next(41) as i64 - 20)
+            })
+            .collect()
+    }
+
+    fn brute_force(values: &[i32], target: i64, k: usize) -> BTreeSet<Vec<i32>> {
+        (0u32..1 << values.len())
+            .filter(|mask| mask.count_ones() as usize == k)
+            .map(|mask| (0..values.len()).filter(|i| mask >> i & 1 == 1).map(|i| values[i]).collect::<Vec<i32>>())
+            .filter(|picked| picked.iter().map(|v| *v as i64).sum::<i64>() == target)
+            .map(|mut picked| { picked.sort_unstable(); picked })
+            .collect()
+    }
+
+    fn check(values: &[i32], target: i64, k: usize) {
+        let solutions = k_sum(values, target, k);
+        for solution in solutions.iter() {
+            assert_eq!(solution.indices.len(), k);
+            assert!(solution.indices.windows(2).all(|w| w[0] < w[1]), "{:?}", solution);
+            assert!(solution.indices.iter().zip(solution.values.iter()).all(|(i, v)| values[*i] == *v));
+        }
+        let found: Vec<Vec<i32>> = solutions.iter().map(|s| s.key()).collect();
+        let expected = brute_force(values, target, k);
+        assert_eq!(found.len(), expected.len(), "{:?} k={} target={}", values, k, target);
+        assert_eq!(found.into_iter().collect::<BTreeSet<_>>(), expected, "{:?} k={} target={}", values, k, target);
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        for (values, target) in random_lists(300) {
+            for k in 0..=values.len() + 1 {
+                check(&values, target, k);
+            }
+        }
+    }
+
+    #[test]
+    fn edge_cases() {
+        assert_eq!(k_sum(&[1, 2], 0, 0).len(), 1);
+        assert!(k_sum(&[1, 2], 3, 0).is_empty());
+        assert!(k_sum(&[1, 2], 3, 3).is_empty());
+        assert_eq!(k_sum(&[4, 7, 7], 7, 1)[0].indices, vec![1]);
+        // Picking equal values at different indices is still one solution
+        let pairs = k_sum(&[5, 5, 5, 5], 10, 2);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].values, vec![5, 5]);
+        check(&[-3, 3, 0, 0, -3, 3], 0, 2);
+        check(&[-3, 3, 0, 0, -3, 3], 0, 4);
+        check(&[1, 1, 1, 1, 1, 1, 1], 5, 5);
+    }
+
+    #[test]
+    fn product_reports_overflow() {
+        let values = vec![1999, -2000, 1500, 1800, 1900, 1700, 1600];
+        let solution = Solution::from_indices(&values, (0..7).collect());
+        assert_eq!(solution.product(), None);
+        let solution = Solution::from_indices(&values, vec![0, 1, 2]);
+        assert_eq!(solution.product(), Some(1999 * -2000 * 1500));
+    }
+}
@@ -1,6 +1,8 @@
+use std::env;
 use std::fs;
-use std::collections::HashMap;
-use combinations::Combinations;
+
+mod ksum;
+mod subset;
 
 fn read_file() -> String {
     let contents = fs::read_to_string("./input.txt")
@@ -16,41 +18,45 @@ fn load_data() -> Vec<i32> {
     return raw    
 }
 
+fn print_subsets(result: &subset::SubsetSum, target: i64, max_size: Option<usize>) {
+    let limit = match max_size { Some(max) => format!(" of at most {} entries", max), None => String::new() };
+    let count = match result.count { u128::MAX => "At least 2^128".to_string(), c => c.to_string() };
+    println!("{} subsets{} sum to {}", count, limit, target);
+    if let Some(smallest) = &result.smallest {
+        let terms: Vec<String> = smallest.values.iter().map(|v| v.to_string()).collect();
+        println!("Smallest: {} = {} at indices {:?}", terms.join(" + "), target, smallest.indices);
+    }
+}
+
 fn main() {
     let values = load_data();
-    let n = values.len();
-    // Part 1
-    for x in 0..n {
-        for y in x+1..n {
-            if values[x] + values[y] == 2020 {
-                println!("{} + {} = 2020", values[x], values[y]);
-                println!("{} * {} = {}", values[x], values[y], values[x] * values[y]);
-            }
+    // Optional arguments: target sum, then number of entries or "any" with an optional size limit
+    let args: Vec<String> = env::args().skip(1).collect();
+    let target: i64 = args.first().map(|t| t.parse().expect("Bad target")).unwrap_or(2020);
+    if args.get(1).map(|a| a.as_str()) == Some("any") {
+        let max_size: Option<usize> = args.get(2).map(|m| m.parse().expect("Bad size limit"));
+        match subset::subset_sum(&values, target, max_size) {
+            Ok(result) => print_subsets(&result, target, max_size),
+            Err(e) => println!("Error: {}", e),
         }
+        return;
     }
-        
-    // Part 2
-    for x in 0..n {
-        for y in x+1..n {
-            for z in y+1..n {
-                if values[x] + values[y] + values[z] == 2020 {
-                    println!("{} + {} + {} = 2020", values[x], values[y], values[z]);
-                    println!("{} * {} * {} = {}", values[x], values[y], values[z], values[x] * values[y] * values[z]);
-                }
-
-            }
+    let sizes: Vec<usize> = match args.get(1) {
+        Some(k) => vec![k.parse().expect("Bad entry count")],
+        None => vec![2, 3],
+    };
+    for k in sizes {
+        let solutions = ksum::k_sum(&values, target, k);
+        if solutions.is_empty() {
+            println!("No {} entries sum to {}", k, target);
         }
-    }
-
-    // Part 1 & 2 version 2
-    for n in 2..4 {
-        let result: HashMap<i32, Vec<i32>> = Combinations::new(values.clone(), n)
-            .filter(|v| (v.iter().fold(0, |a, b| a + *b) == 2020))
-            .map(|v| (v.iter().fold(1, |a, b| a * *b), v.clone()))
-            .collect();
-
-        for (key, val) in result.into_iter() {
-            println!("{}: {} {:?}", n, key, val);
+        for solution in solutions.iter() {
+            let terms: Vec<String> = solution.values.iter().map(|v| v.to_string()).collect();
+            println!("{} = {} at indices {:?}", terms.join(" + "), target, solution.indices);
+            match solution.product() {
+                Some(product) => println!("{} = {}", terms.join(" * "), product),
+                None => println!("{} = overflow", terms.join(" * ")),
+            }
         }
     }
 }
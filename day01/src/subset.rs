@@ -0,0 +1,228 @@
+use std::ops::Range;
+
+use crate::ksum::Solution;
+
+// Upper bound on the per-entry reconstruction table, in bits
+const MAX_TABLE_BITS: usize = 1 << 31;
+const UNREACHABLE: u32 = u32::MAX;
+
+#[derive(Debug)]
+pub struct SubsetSum {
+    pub smallest: Option<Solution>,
+    // Number of non-empty subsets hitting the target, saturating at u128::MAX
+    pub count: u128,
+}
+
+// Dense table over every sum a subset can reach, offset so the lowest is index 0.
+// Without negative entries nothing above the target can lead back to it.
+struct Sums {
+    low: i64,
+    width: usize,
+}
+
+impl Sums {
+    fn new(values: &[i32], target: i64) -> Sums {
+        let low: i64 = values.iter().filter(|v| **v < 0).map(|v| *v as i64).sum();
+        let mut high: i64 = values.iter().filter(|v| **v > 0).map(|v| *v as i64).sum();
+        if low == 0 {
+            high = high.min(target.max(0));
+        }
+        Sums{low, width: (high - low + 1) as usize}
+    }
+
+    fn index(&self, sum: i64) -> Option<usize> {
+        let idx = sum - self.low;
+        match idx >= 0 && (idx as usize) < self.width { true => Some(idx as usize), false => None }
+    }
+
+    // Visits (to, from) index pairs for adding `value` to every sum within `reach`,
+    // ordered so an in-place update only reads entries the current value has not
+    // touched yet, then widens `reach` to cover the new sums
+    fn for_each_shift<F>(&self, value: i32, reach: &mut Reach, mut f: F)
+    where F: FnMut(usize, usize) {
+        if let Some((from, to)) = self.shifted_ranges(value, reach) {
+            let offset = to.start as isize - from.start as isize;
+            match value >= 0 {
+                true => from.clone().rev().for_each(|idx| f((idx as isize + offset) as usize, idx)),
+                false => from.clone().for_each(|idx| f((idx as isize + offset) as usize, idx)),
+            }
+            reach.widen(&to);
+        }
+    }
+
+    // Source and destination ranges for adding `value` to every sum within `reach`
+    fn shifted_ranges(&self, value: i32, reach: &Reach) -> Option<(Range<usize>, Range<usize>)> {
+        let (low, high) = reach.span?;
+        let shift = value as i64;
+        let from_low = (low as i64).max(-shift);
+        let from_high = (high as i64).min(self.width as i64 - 1 - shift);
+        match from_low <= from_high {
+            true => Some((from_low as usize..from_high as usize + 1,
+                (from_low + shift) as usize..(from_high + shift) as usize + 1)),
+            false => None,
+        }
+    }
+}
+
+// Hull of the table indices that may hold something other than the initial value
+#[derive(Debug, Clone, Copy)]
+struct Reach {
+    span: Option<(usize, usize)>,
+}
+
+impl Reach {
+    fn at(idx: usize) -> Reach {
+        Reach{span: Some((idx, idx))}
+    }
+
+    fn widen(&mut self, range: &Range<usize>) {
+        self.span = match self.span {
+            Some((low, high)) => Some((low.min(range.start), high.max(range.end - 1))),
+            None => Some((range.start, range.end - 1)),
+        };
+    }
+}
+
+// Fewest entries summing to the target, together with how many subsets do so,
+// optionally only counting subsets of at most `max_size` entries
+pub fn subset_sum(values: &[i32], target: i64, max_size: Option<usize>) -> Result<SubsetSum, String> {
+    let sums = Sums::new(values, target);
+    let goal = match sums.index(target) {
+        Some(goal) => goal,
+        None => return Ok(SubsetSum{smallest: None, count: 0}),
+    };
+    let words = sums.width.div_ceil(64);
+    if words.saturating_mul(64).saturating_mul(values.len()) > MAX_TABLE_BITS {
+        return Err(format!("Sums span {} values over {} entries, too many to track", sums.width, values.len()));
+    }
+    let smallest = smallest_subset(values, target, &sums, goal, words)
+        .filter(|s| max_size.is_none_or(|max| s.indices.len() <= max));
+    let count = match max_size {
+        Some(max) => count_bounded(values, &sums, goal, max),
+        None => count_all(values, &sums, goal) - (target == 0) as u128,
+    };
+    Ok(SubsetSum{smallest, count})
+}
+
+// Keeps one bit per entry and sum recording whether that entry improved the sum,
+// which lets the optimum be walked back from the last entry to the first
+fn smallest_subset(values: &[i32], target: i64, sums: &Sums, goal: usize, words: usize) -> Option<Solution> {
+    let mut best: Vec<u32> = vec![UNREACHABLE; sums.width];
+    let mut took: Vec<u64> = vec![0; words * values.len()];
+    let mut reach = Reach::at(sums.index(0).unwrap());
+    for (item, value) in values.iter().enumerate() {
+        let row = &mut took[item * words..(item + 1) * words];
+        sums.for_each_shift(*value, &mut reach, |to, from| {
+            if best[from] != UNREACHABLE && best[from] + 1 < best[to] {
+                best[to] = best[from] + 1;
+                row[to / 64] |= 1 << (to % 64);
+            }
+        });
+        if let Some(alone) = sums.index(*value as i64) {
+            if best[alone] > 1 {
+                best[alone] = 1;
+                row[alone / 64] |= 1 << (alone % 64);
+            }
+        }
+    }
+    if best[goal] == UNREACHABLE {
+        return None;
+    }
+    let mut remaining = best[goal];
+    let mut sum = target;
+    let mut indices: Vec<usize> = Vec::with_capacity(remaining as usize);
+    for item in (0..values.len()).rev() {
+        if remaining == 0 {
+            break;
+        }
+        let at = sums.index(sum).unwrap();
+        if took[item * words + at / 64] & (1 << (at % 64)) != 0 {
+            indices.push(item);
+            sum -= values[item] as i64;
+            remaining -= 1;
+        }
+    }
+    Some(Solution::from_indices(values, indices))
+}
+
+fn count_all(values: &[i32], sums: &Sums, goal: usize) -> u128 {
+    let mut counts: Vec<u128> = vec![0; sums.width];
+    let zero = sums.index(0).unwrap();
+    counts[zero] = 1;
+    let mut reach = Reach::at(zero);
+    for value in values.iter() {
+        sums.for_each_shift(*value, &mut reach, |to, from| counts[to] = counts[to].saturating_add(counts[from]));
+    }
+    counts[goal]
+}
+
+fn count_bounded(values: &[i32], sums: &Sums, goal: usize, max_size: usize) -> u128 {
+    // No subset is larger than the whole list
+    let max_size = max_size.min(values.len());
+    let mut counts: Vec<Vec<u128>> = vec![vec![0; sums.width]; max_size + 1];
+    let zero = sums.index(0).unwrap();
+    counts[0][zero] = 1;
+    let mut reaches: Vec<Reach> = vec![Reach{span: None}; max_size + 1];
+    reaches[0] = Reach::at(zero);
+    for value in values.iter() {
+        for size in (1..=max_size).rev() {
+            if let Some((from, to)) = sums.shifted_ranges(*value, &reaches[size - 1]) {
+                let (fewer, more) = counts.split_at_mut(size);
+                let (fewer, more) = (&fewer[size - 1][from], &mut more[0][to.clone()]);
+                more.iter_mut().zip(fewer.iter()).for_each(|(m, f)| *m = m.saturating_add(*f));
+                reaches[size].widen(&to);
+            }
+        }
+    }
+    counts.iter().skip(1).map(|c| c[goal]).fold(0, |a: u128, b| a.saturating_add(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ksum::tests::random_lists;
+
+    // Smallest size and number of non-empty subsets hitting the target with at most `max_size` entries
+    fn brute_force(values: &[i32], target: i64, max_size: Option<usize>) -> (Option<usize>, u128) {
+        let sizes: Vec<usize> = (1u32..1 << values.len())
+            .filter(|mask| max_size.is_none_or(|max| mask.count_ones() as usize <= max))
+            .filter(|mask| (0..values.len()).filter(|i| mask >> i & 1 == 1).map(|i| values[i] as i64).sum::<i64>() == target)
+            .map(|mask| mask.count_ones() as usize)
+            .collect();
+        (sizes.iter().min().cloned(), sizes.len() as u128)
+    }
+
+    fn check(values: &[i32], target: i64, max_size: Option<usize>) {
+        let result = subset_sum(values, target, max_size).unwrap();
+        let (smallest, count) = brute_force(values, target, max_size);
+        let context = format!("{:?} target={} max={:?}", values, target, max_size);
+        assert_eq!(result.count, count, "{}", context);
+        assert_eq!(result.smallest.as_ref().map(|s| s.indices.len()), smallest, "{}", context);
+        if let Some(solution) = result.smallest {
+            assert!(solution.indices.windows(2).all(|w| w[0] < w[1]), "{}", context);
+            assert!(solution.indices.iter().zip(solution.values.iter()).all(|(i, v)| values[*i] == *v));
+            assert_eq!(solution.values.iter().map(|v| *v as i64).sum::<i64>(), target, "{}", context);
+        }
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        for (values, target) in random_lists(300) {
+            check(&values, target, None);
+            for max in 0..=values.len() + 1 {
+                check(&values, target, Some(max));
+            }
+        }
+    }
+
+    #[test]
+    fn edge_cases() {
+        check(&[], 0, None);
+        check(&[0, 0], 0, None);
+        check(&[3, -3, 0], 0, None);
+        check(&[5, 5, 5, 5], 10, Some(2));
+        check(&[1, 1, 1, 1, 1, 1], 4, Some(3));
+        assert_eq!(subset_sum(&[1, 2, 3], 7, None).unwrap().count, 0);
+        assert_eq!(subset_sum(&[1, 2, 3], 3, Some(usize::MAX)).unwrap().count, 2);
+    }
+}
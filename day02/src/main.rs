@@ -1,10 +1,19 @@
-use std::fs;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use regex::Regex;
 
-fn read_data() -> String {
-    let lines = fs::read_to_string("./input.txt")
-        .unwrap();
-    return lines
+// Lines handed to a worker at a time; at most a few chunks per worker are in flight
+const CHUNK_LINES: usize = 8192;
+
+fn open_input(path: &str) -> Box<dyn BufRead + Send> {
+    match path {
+        "-" => Box::new(BufReader::new(io::stdin())),
+        _ => Box::new(BufReader::new(File::open(path).unwrap_or_else(|e| panic!("{}: {}", path, e)))),
+    }
 }
 
 trait InRanged {
@@ -18,46 +27,342 @@ impl InRanged for usize {
 }
 
 trait Password {
-    fn valid(&self, chr: char, idx1: usize, idx2: usize) -> bool;
+    fn valid(&self, chr: char, idx1: usize, idx2: usize) -> Result<bool, String>;
 }
 
 impl Password for String {
-    fn valid(&self, chr: char, idx1: usize, idx2: usize) -> bool {
-        let mut matches = 0;
-        let mut chars = self.chars();
-        for idx in 1..idx2 + 1 {
-            let c = chars.next().unwrap();
-            if idx == idx1 && c == chr {
-                matches += 1;
-            } else if idx == idx2 && c == chr {
-                matches += 1;
+    // Positions are 1-based and must both exist in the password
+    fn valid(&self, chr: char, idx1: usize, idx2: usize) -> Result<bool, String> {
+        let at = |idx: usize| match idx {
+            0 => Err("position 0 is not 1-based".to_string()),
+            _ => self.chars().nth(idx - 1)
+                .ok_or_else(|| format!("position {} beyond length {}", idx, self.chars().count())),
+        };
+        let (first, second) = (at(idx1)?, at(idx2)?);
+        Ok((first == chr) != (second == chr))
+    }
+}
+
+// One line of the password database: `low-high chr: password`
+#[derive(Debug)]
+struct Entry {
+    low: usize,
+    high: usize,
+    chr: char,
+    password: String,
+}
+
+impl Entry {
+    fn from_line(line: &str, re: &Regex) -> Result<Entry, String> {
+        let caps = re.captures(line).ok_or("Could not parse line")?;
+        let number = |idx: usize| caps[idx].parse::<usize>().map_err(|e| format!("{}: {}", &caps[idx], e));
+        Ok(Entry{
+            low: number(1)?,
+            high: number(2)?,
+            chr: caps[3].chars().next().unwrap(),
+            password: String::from(&caps[4]),
+        })
+    }
+}
+
+trait Policy: Send + Sync {
+    fn name(&self) -> String;
+    // Err describes why the entry fails the policy
+    fn check(&self, entry: &Entry) -> Result<(), String>;
+}
+
+// The letter occurs between low and high times
+struct CountInRange;
+
+impl Policy for CountInRange {
+    fn name(&self) -> String {
+        "count".to_string()
+    }
+
+    fn check(&self, entry: &Entry) -> Result<(), String> {
+        let count = entry.password.matches(entry.chr).count();
+        match count.in_range(entry.low, entry.high) {
+            true => Ok(()),
+            false => Err(format!("{} occurs {} times, not {}-{}", entry.chr, count, entry.low, entry.high)),
+        }
+    }
+}
+
+// The letter is at exactly one of the two positions
+struct ExactlyOnePosition;
+
+impl Policy for ExactlyOnePosition {
+    fn name(&self) -> String {
+        "positions".to_string()
+    }
+
+    fn check(&self, entry: &Entry) -> Result<(), String> {
+        match entry.password.valid(entry.chr, entry.low, entry.high)? {
+            true => Ok(()),
+            false => Err(format!("{} not at exactly one of {} and {}", entry.chr, entry.low, entry.high)),
+        }
+    }
+}
+
+struct MinDistinct {
+    min: usize,
+}
+
+impl Policy for MinDistinct {
+    fn name(&self) -> String {
+        format!("distinct:{}", self.min)
+    }
+
+    fn check(&self, entry: &Entry) -> Result<(), String> {
+        let mut chars: Vec<char> = entry.password.chars().collect();
+        chars.sort_unstable();
+        chars.dedup();
+        match chars.len() >= self.min {
+            true => Ok(()),
+            false => Err(format!("{} distinct letters, fewer than {}", chars.len(), self.min)),
+        }
+    }
+}
+
+struct Forbidden {
+    substrings: Vec<String>,
+}
+
+impl Policy for Forbidden {
+    fn name(&self) -> String {
+        format!("forbid:{}", self.substrings.join(","))
+    }
+
+    fn check(&self, entry: &Entry) -> Result<(), String> {
+        match self.substrings.iter().find(|s| entry.password.contains(s.as_str())) {
+            Some(s) => Err(format!("contains {}", s)),
+            None => Ok(()),
+        }
+    }
+}
+
+// The letter is at `min` or more of the given 1-based positions; positions past
+// the end of the password simply don't match
+struct AtLeastPositions {
+    min: usize,
+    positions: Vec<usize>,
+}
+
+impl Policy for AtLeastPositions {
+    fn name(&self) -> String {
+        let positions: Vec<String> = self.positions.iter().map(|p| p.to_string()).collect();
+        format!("at-least:{}@{}", self.min, positions.join(","))
+    }
+
+    fn check(&self, entry: &Entry) -> Result<(), String> {
+        let chars: Vec<char> = entry.password.chars().collect();
+        let hits = self.positions
+            .iter()
+            .filter(|p| **p > 0 && chars.get(**p - 1) == Some(&entry.chr))
+            .count();
+        match hits >= self.min {
+            true => Ok(()),
+            false => Err(format!("{} at {} of the positions, fewer than {}", entry.chr, hits, self.min)),
+        }
+    }
+}
+
+// Policy specs: count, positions, distinct:N, forbid:ab,cd, at-least:N@P1,P2,...
+fn parse_policy(spec: &str) -> Result<Box<dyn Policy>, String> {
+    let number = |s: &str| s.trim().parse::<usize>().map_err(|e| format!("{} in {}: {}", s, spec, e));
+    let (kind, args) = match spec.find(':') {
+        Some(idx) => (&spec[..idx], &spec[idx + 1..]),
+        None => (spec, ""),
+    };
+    match kind {
+        "count" => Ok(Box::new(CountInRange)),
+        "positions" => Ok(Box::new(ExactlyOnePosition)),
+        "distinct" => Ok(Box::new(MinDistinct{min: number(args)?})),
+        "forbid" => {
+            let substrings: Vec<String> = args.split(',').filter(|s| !s.is_empty()).map(String::from).collect();
+            match substrings.is_empty() {
+                true => Err(format!("No substrings in {}", spec)),
+                false => Ok(Box::new(Forbidden{substrings})),
             }
+        },
+        "at-least" => {
+            let (min, positions) = args.split_once('@').ok_or(format!("Expected N@positions in {}", spec))?;
+            let positions = positions.split(',').map(number).collect::<Result<Vec<usize>, String>>()?;
+            Ok(Box::new(AtLeastPositions{min: number(min)?, positions}))
+        },
+        _ => Err(format!("Unknown policy {}", spec)),
+    }
+}
+
+struct Chunk {
+    index: usize,
+    first_line: usize,
+    lines: Vec<String>,
+}
+
+// A line failing a policy, or failing to parse when there is no policy
+struct Failure {
+    line_no: usize,
+    policy: Option<usize>,
+    reason: String,
+}
+
+impl Failure {
+    fn policy_name(&self, policies: &[Box<dyn Policy>]) -> String {
+        match self.policy {
+            Some(idx) => policies[idx].name(),
+            None => "parse".to_string(),
         }
-        return matches == 1;
     }
 }
 
+// One row per failing line: the line number and the policies it fails
+fn write_failing_lines<W: Write>(out: &mut W, failures: &[Failure], policies: &[Box<dyn Policy>]) -> io::Result<()> {
+    let mut idx = 0;
+    while idx < failures.len() {
+        let line_no = failures[idx].line_no;
+        let names: Vec<String> = failures[idx..]
+            .iter()
+            .take_while(|f| f.line_no == line_no)
+            .map(|f| f.policy_name(policies))
+            .collect();
+        idx += names.len();
+        writeln!(out, "{}\t{}", line_no, names.join(","))?;
+    }
+    Ok(())
+}
+
+struct ChunkResult {
+    index: usize,
+    lines: usize,
+    valid: Vec<usize>,
+    failures: Vec<Failure>,
+}
+
+fn check_chunk(chunk: Chunk, re: &Regex, policies: &[Box<dyn Policy>]) -> ChunkResult {
+    let mut valid: Vec<usize> = vec![0; policies.len()];
+    let mut failures: Vec<Failure> = Vec::new();
+    for (offset, line) in chunk.lines.iter().enumerate() {
+        let line_no = chunk.first_line + offset;
+        let entry = match Entry::from_line(line.trim(), re) {
+            Ok(entry) => entry,
+            Err(reason) => {
+                failures.push(Failure{line_no, policy: None, reason});
+                continue;
+            },
+        };
+        for (idx, policy) in policies.iter().enumerate() {
+            match policy.check(&entry) {
+                Ok(()) => valid[idx] += 1,
+                Err(reason) => failures.push(Failure{line_no, policy: Some(idx), reason}),
+            }
+        }
+    }
+    ChunkResult{index: chunk.index, lines: chunk.lines.len(), valid, failures}
+}
+
+// Reads chunks on one thread, checks them on a worker per core and hands results
+// back in input order, so memory stays bounded by the chunks in flight
+fn validate<F>(input: Box<dyn BufRead + Send>, re: &Regex, policies: &[Box<dyn Policy>], mut collect: F)
+where F: FnMut(ChunkResult) {
+    let workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let (chunk_tx, chunk_rx) = mpsc::sync_channel::<Chunk>(workers * 2);
+    let (result_tx, result_rx) = mpsc::sync_channel::<ChunkResult>(workers * 2);
+    let chunk_rx = Arc::new(Mutex::new(chunk_rx));
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let mut lines = input.lines();
+            let mut first_line = 1;
+            for index in 0.. {
+                let chunk: Vec<String> = lines
+                    .by_ref()
+                    .take(CHUNK_LINES)
+                    .map(|l| l.unwrap_or_else(|e| panic!("Line {}: {}", first_line, e)))
+                    .collect();
+                if chunk.is_empty() {
+                    break;
+                }
+                let count = chunk.len();
+                if chunk_tx.send(Chunk{index, first_line, lines: chunk}).is_err() {
+                    break;
+                }
+                first_line += count;
+            }
+        });
+        for _ in 0..workers {
+            let (chunk_rx, result_tx) = (Arc::clone(&chunk_rx), result_tx.clone());
+            scope.spawn(move || loop {
+                let chunk = chunk_rx.lock().unwrap().recv();
+                match chunk {
+                    Ok(chunk) => result_tx.send(check_chunk(chunk, re, policies)).unwrap(),
+                    Err(_) => break,
+                }
+            });
+        }
+        drop(result_tx);
+        let mut pending: BTreeMap<usize, ChunkResult> = BTreeMap::new();
+        let mut next = 0;
+        for result in result_rx.iter() {
+            pending.insert(result.index, result);
+            while let Some(result) = pending.remove(&next) {
+                collect(result);
+                next += 1;
+            }
+        }
+    });
+}
+
 fn main() {
     let re = Regex::new(r"^(\d+)-(\d+) ([a-z]): ([a-z]+)$").unwrap();
-    let data = read_data();
-
-    // Part 1 & Part 2
-    let mut valid_part1: usize = 0;
-    let mut valid_part2: usize = 0;
-    for line in data.lines().map(|l| l.trim()) {
-        let caps = re.captures(line)
-            .expect("Could not parse line");
-        let pwd =  String::from(&caps[4]);
-        let chr = caps[3].chars().next().unwrap();
-        let low = caps[1].parse::<usize>().unwrap();
-        let high = caps[2].parse::<usize>().unwrap();
-        if pwd.matches(chr).count().in_range(low, high) {
-            valid_part1+=1;
-        }
-        if pwd.valid(chr, low, high) {
-            valid_part2+=1;
-        }
-    }
-    println!("{} valid passwords part 1", valid_part1);
-    println!("{} valid passwords part 2", valid_part2);
+    let report = false;
+
+    // Arguments: policy specs, plus --input PATH (- for stdin) and --failures PATH
+    let mut specs: Vec<String> = Vec::new();
+    let mut input_path = "./input.txt".to_string();
+    let mut failures_path: Option<String> = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => input_path = args.next().expect("Missing input path"),
+            "--failures" => failures_path = Some(args.next().expect("Missing failures path")),
+            _ => specs.push(arg),
+        }
+    }
+    // Policies default to part 1 & part 2
+    if specs.is_empty() {
+        specs = vec!["count".to_string(), "positions".to_string()];
+    }
+    let policies: Vec<Box<dyn Policy>> = specs
+        .iter()
+        .map(|s| parse_policy(s))
+        .collect::<Result<_, String>>()
+        .unwrap_or_else(|e| panic!("{}", e));
+
+    let mut failures_file = failures_path
+        .as_ref()
+        .map(|path| BufWriter::new(File::create(path).unwrap_or_else(|e| panic!("{}: {}", path, e))));
+    let mut lines: usize = 0;
+    let mut unparseable: usize = 0;
+    let mut valid: Vec<usize> = vec![0; policies.len()];
+    let mut failing: Vec<usize> = vec![0; policies.len()];
+    validate(open_input(&input_path), &re, &policies, |result| {
+        lines += result.lines;
+        valid.iter_mut().zip(result.valid.iter()).for_each(|(total, v)| *total += v);
+        for failure in result.failures.iter() {
+            match failure.policy {
+                Some(idx) => failing[idx] += 1,
+                None => unparseable += 1,
+            }
+            if report {
+                println!("  line {}: {}: {}", failure.line_no, failure.policy_name(&policies), failure.reason);
+            }
+        }
+        if let Some(file) = failures_file.as_mut() {
+            write_failing_lines(file, &result.failures, &policies).expect("Could not write failures");
+        }
+    });
+    println!("{} lines read, {} unparseable", lines, unparseable);
+    for (idx, policy) in policies.iter().enumerate() {
+        println!("{} valid passwords by {}, {} failing", valid[idx], policy.name(), failing[idx]);
+    }
 }
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+use std::env;
 use std::fs;
 
 fn read_file() -> String {
@@ -39,7 +41,190 @@ impl Slope {
     }
 }
 
+// Trees as one bitset per row, bit x set when column x holds a tree
+struct Forest {
+    rows: Vec<Vec<u64>>,
+    width: usize,
+}
+
+impl Forest {
+    fn from_string(data: &str) -> Forest {
+        let lines: Vec<&str> = data.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).collect();
+        let width = lines.first().map(|l| l.len()).unwrap_or(0);
+        let rows = lines
+            .iter()
+            .map(|line| {
+                let mut bits: Vec<u64> = vec![0; width.div_ceil(64)];
+                for (x, _) in line.chars().enumerate().filter(|(_, c)| *c == '#') {
+                    bits[x / 64] |= 1 << (x % 64);
+                }
+                bits
+            })
+            .collect();
+        Forest{rows, width}
+    }
+
+    fn is_tree(&self, x: usize, y: usize) -> bool {
+        let x = x % self.width;
+        self.rows[y][x / 64] & (1 << (x % 64)) != 0
+    }
+
+    fn trees(&self, step_x: usize, step_y: usize) -> u64 {
+        (0..self.rows.len())
+            .step_by(step_y)
+            .enumerate()
+            .filter(|(step, y)| self.is_tree(step * step_x % self.width, *y))
+            .count() as u64
+    }
+}
+
+// Counts trees for every slope right 0..=max_x, down 1..=max_y
+fn optimise(data: &str, max_x: usize, max_y: usize) {
+    let forest = Forest::from_string(data);
+    let mut results: Vec<(usize, usize, u64)> = Vec::with_capacity((max_x + 1) * max_y);
+    for step_y in 1..=max_y {
+        for step_x in 0..=max_x {
+            let trees = forest.trees(step_x, step_y);
+            println!("{}, {} gave {} trees", step_x, step_y, trees);
+            results.push((step_x, step_y, trees));
+        }
+    }
+    let fewest = results.iter().map(|r| r.2).min().unwrap_or(0);
+    let most = results.iter().map(|r| r.2).max().unwrap_or(0);
+    for (label, count) in [("Fewest", fewest), ("Most", most)].iter() {
+        let slopes: Vec<String> = results
+            .iter()
+            .filter(|r| r.2 == *count)
+            .map(|r| format!("{},{}", r.0, r.1))
+            .collect();
+        let more = match slopes.len() > 10 { true => format!(" and {} more", slopes.len() - 10), false => String::new() };
+        println!("{} trees {} on slopes {}{}", label, count, slopes[..slopes.len().min(10)].join(" "), more);
+    }
+}
+
+// Hit and miss symbols per overlaid slope, the first as in the puzzle statement
+const MARKS: [(char, char); 6] = [('X', 'O'), ('H', 'h'), ('T', 't'), ('B', 'b'), ('K', 'k'), ('V', 'v')];
+const COLOURS: [[u8; 3]; 6] = [[220, 40, 40], [240, 160, 20], [40, 120, 240], [200, 60, 200], [20, 180, 180], [120, 200, 40]];
+
+// Slopes landing on each cell of the tiled forest
+fn trajectories(forest: &Forest, slopes: &[(usize, usize)]) -> HashMap<(usize, usize), Vec<usize>> {
+    let mut landings: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for (idx, (step_x, step_y)) in slopes.iter().enumerate() {
+        for (step, y) in (0..forest.rows.len()).step_by(*step_y).enumerate() {
+            landings.entry((step * step_x, y)).or_default().push(idx);
+        }
+    }
+    landings
+}
+
+// Forest tiled as far right as any slope travels, cells hit by several slopes shown as `*`
+fn render(forest: &Forest, slopes: &[(usize, usize)], colour: bool) -> Vec<String> {
+    let landings = trajectories(forest, slopes);
+    let width = landings.keys().map(|(x, _)| x + 1).max().unwrap_or(0).max(forest.width);
+    (0..forest.rows.len())
+        .map(|y| (0..width)
+            .map(|x| {
+                let tree = forest.is_tree(x, y);
+                match landings.get(&(x, y)).map(|l| l.as_slice()) {
+                    None => match tree { true => "#".to_string(), false => ".".to_string() },
+                    Some([idx]) => {
+                        let (hit, miss) = MARKS[idx % MARKS.len()];
+                        let mark = match tree { true => hit, false => miss };
+                        match colour {
+                            true => {
+                                let [r, g, b] = COLOURS[idx % COLOURS.len()];
+                                format!("\x1b[1;38;2;{};{};{}m{}\x1b[0m", r, g, b, mark)
+                            },
+                            false => mark.to_string(),
+                        }
+                    },
+                    Some(_) => "*".to_string(),
+                }
+            })
+            .collect::<String>())
+        .collect()
+}
+
+// Each cell becomes a scale x scale block: trees dark, open ground light, landings in
+// their slope's colour, darkened on a hit
+fn export_ppm(forest: &Forest, slopes: &[(usize, usize)], scale: usize, path: &str) -> std::io::Result<()> {
+    let landings = trajectories(forest, slopes);
+    let columns = landings.keys().map(|(x, _)| x + 1).max().unwrap_or(0).max(forest.width);
+    let (width, height) = (columns * scale, forest.rows.len() * scale);
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for y in 0..forest.rows.len() {
+        let row: Vec<u8> = (0..columns)
+            .flat_map(|x| {
+                let tree = forest.is_tree(x, y);
+                let colour = match (landings.get(&(x, y)), tree) {
+                    (Some(l), true) if l.len() == 1 => COLOURS[l[0] % COLOURS.len()].map(|c| c / 2),
+                    (Some(l), false) if l.len() == 1 => COLOURS[l[0] % COLOURS.len()],
+                    (Some(_), _) => [0, 0, 0],
+                    (None, true) => [30, 90, 40],
+                    (None, false) => [235, 240, 235],
+                };
+                std::iter::repeat_n(colour, scale)
+            })
+            .flatten()
+            .collect();
+        for _ in 0..scale {
+            data.extend(row.iter());
+        }
+    }
+    std::fs::write(path, data)
+}
+
+fn parse_slope(spec: &str) -> (usize, usize) {
+    let (x, y) = spec.split_once(',').unwrap_or_else(|| panic!("Slope {} is not RIGHT,DOWN", spec));
+    let slope = (x.parse().expect("Bad slope"), y.parse().expect("Bad slope"));
+    if slope.1 == 0 {
+        panic!("Slope {} never moves down", spec);
+    }
+    slope
+}
+
 fn main() {
+    // Slope optimiser: optimise MAX_RIGHT MAX_DOWN
+    // Path renderer: render [--colour] [--export PATH] RIGHT,DOWN...
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(|a| a.as_str()) == Some("render") {
+        let forest = Forest::from_string(&read_file());
+        let mut slopes: Vec<(usize, usize)> = Vec::new();
+        let mut colour = false;
+        let mut export: Option<String> = None;
+        let mut rest = args.iter().skip(1);
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "--colour" => colour = true,
+                "--export" => export = Some(rest.next().expect("Missing export path").clone()),
+                _ => slopes.push(parse_slope(arg)),
+            }
+        }
+        if slopes.is_empty() {
+            slopes.push((3, 1));
+        }
+        match export {
+            Some(path) => export_ppm(&forest, &slopes, 4, &path).expect("Could not write image"),
+            None => render(&forest, &slopes, colour).iter().for_each(|line| println!("{}", line)),
+        }
+        if slopes.len() > 1 {
+            let legend: Vec<String> = slopes
+                .iter()
+                .enumerate()
+                .map(|(idx, (x, y))| {
+                    let (hit, miss) = MARKS[idx % MARKS.len()];
+                    format!("{}/{} {},{}", hit, miss, x, y)
+                })
+                .collect();
+            println!("{}  * shared", legend.join("  "));
+        }
+        return;
+    }
+    if args.first().map(|a| a.as_str()) == Some("optimise") {
+        let bound = |idx: usize| args.get(idx).map(|a| a.parse().expect("Bad slope bound")).unwrap_or(10);
+        optimise(&read_file(), bound(1), bound(2));
+        return;
+    }
     // Puzzle 1 & 2
     let mut slopes: [Slope; 5] = [
         Slope {step_x: 1, step_y: 1, trees: 0, x: 0, y: 0},
@@ -1,204 +1,478 @@
+use std::env;
+use std::fmt;
 use std::fs;
-use std::ops::AddAssign;
+use std::str::FromStr;
 use regex::Regex;
 
-fn read_data() -> String {
-    fs::read_to_string("./input.txt").unwrap()
+fn read_data(path: &str) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+// Built-in schema for North Pole passports
+const AOC_SCHEMA: &str = include_str!("../passport.toml");
+
+// A field that broke the schema; `kind` groups violations in the summary
+#[derive(Debug, Clone)]
+struct Violation {
+    field: String,
+    kind: &'static str,
+    detail: String,
+}
+
+impl Violation {
+    fn new(field: &str, kind: &'static str, detail: String) -> Violation {
+        Violation{field: field.to_string(), kind, detail}
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.detail)
+    }
 }
 
 #[derive(Debug)]
-struct Pass {
-    byr: usize,
-    iyr: usize,
-    eyr: usize,
-    hgt: usize,
-    hcl: usize,
-    ecl: usize,
-    pid: usize,
-    cid: usize,
-}
-
-impl Pass {
-    fn is_passport(&self) -> bool {
-        self.byr == 1 &&
-        self.iyr == 1 &&
-        self.eyr == 1 && 
-        self.hgt == 1 &&
-        self.hcl == 1 &&
-        self.ecl == 1 &&
-        self.pid == 1 &&
-        self.cid == 1
-    }
-
-    fn is_northpole_creds(&self) -> bool {
-        self.byr == 1 &&
-        self.iyr == 1 &&
-        self.eyr == 1 && 
-        self.hgt == 1 &&
-        self.hcl == 1 &&
-        self.ecl == 1 &&
-        self.pid == 1 &&
-        self.cid == 0
-    }
-}
-
-impl AddAssign for Pass {
-    fn add_assign(&mut self, other: Self) {
-        self.byr += other.byr;
-        self.iyr += other.iyr;
-        self.eyr += other.eyr;
-        self.hgt += other.hgt;
-        self.hcl += other.hcl;
-        self.ecl += other.ecl;
-        self.pid += other.pid;
-        self.cid += other.cid;
-    }
-}
-
-fn field_index(data: &Vec<&str>, pat: &str) -> Option<usize> {
-    let mut idx = 0;
-    for v in data {
-        if v.starts_with(pat) {
-            return Some(idx);
+enum Validator {
+    Range(i64, i64),
+    Units(Vec<(String, i64, i64)>),
+    Pattern(Regex, String),
+    OneOf(Vec<String>),
+}
+
+impl Validator {
+    // Err holds the violation kind and a description of the offending value
+    fn check(&self, value: &str) -> Result<(), (&'static str, String)> {
+        match self {
+            Validator::Range(low, high) => check_range(value, "", *low, *high),
+            Validator::Units(units) => {
+                let (number, unit, low, high) = units
+                    .iter()
+                    .find_map(|(unit, low, high)| value.strip_suffix(unit.as_str()).map(|n| (n, unit, low, high)))
+                    .ok_or_else(|| {
+                        let names: Vec<&str> = units.iter().map(|(unit, _, _)| unit.as_str()).collect();
+                        ("no unit", format!("{} has no unit of {}", value, names.join(" ")))
+                    })?;
+                check_range(number, unit, *low, *high)
+            },
+            Validator::Pattern(re, pattern) => match re.is_match(value) {
+                true => Ok(()),
+                false => Err(("pattern", format!("{} does not match {}", value, pattern))),
+            },
+            Validator::OneOf(options) => match options.iter().any(|o| o == value) {
+                true => Ok(()),
+                false => Err(("not allowed", format!("{} is not one of {}", value, options.join(" ")))),
+            },
         }
-        idx += 1;
     }
-    return None
 }
 
-fn field_value_numeric(field: &str) -> Option<usize> {
-    let (_, num) = field.split_at(4);
-    match num.parse::<usize>() {
-        Ok(val) => Some(val),
-        Err(_) => None
+fn check_range(number: &str, unit: &str, low: i64, high: i64) -> Result<(), (&'static str, String)> {
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return Err(("not a number", format!("{}{} is not a number", number, unit)));
     }
+    let shown = match unit.is_empty() { true => number.to_string(), false => format!("{} {}", number, unit) };
+    match number.parse::<i64>() {
+        Ok(value) if value < low => Err(("below range", format!("{} below {}..={}", shown, low, high))),
+        Ok(value) if value <= high => Ok(()),
+        _ => Err(("above range", format!("{} above {}..={}", shown, low, high))),
+    }
+}
+
+#[derive(Debug)]
+struct FieldRule {
+    key: String,
+    required: bool,
+    validators: Vec<Validator>,
+}
+
+#[derive(Debug)]
+struct Schema {
+    name: String,
+    allow_unknown: bool,
+    fields: Vec<FieldRule>,
 }
 
-fn is_field(val: &Option<usize>) -> bool {
-    match val {
-        Some(_) => true,
-        None => false
+fn toml_range(value: &toml::Value, context: &str) -> Result<(i64, i64), String> {
+    match value.as_array().map(|a| a.as_slice()) {
+        Some([low, high]) => match (low.as_integer(), high.as_integer()) {
+            (Some(low), Some(high)) => Ok((low, high)),
+            _ => Err(format!("{}: range bounds must be integers", context)),
+        },
+        _ => Err(format!("{}: expected [low, high]", context)),
     }
 }
 
-fn validate_numeric_range(
-    idx: Option<usize>,
-    data: &Vec<&str>,
-    low: usize,
-    high: usize,
-) -> usize {
-    match idx {
-        None => 0,
-        Some(i) => {
-            match field_value_numeric(data[i]) {
-                None => 0,
-                Some(val) => {
-                    if low <= val && val <= high {
-                        return 1
+impl Schema {
+    fn from_toml(data: &str) -> Result<Schema, String> {
+        let table = data.parse::<toml::Table>().map_err(|e| e.to_string())?;
+        let name = table.get("name").and_then(|n| n.as_str()).unwrap_or("document").to_string();
+        let allow_unknown = table.get("allow_unknown").and_then(|a| a.as_bool()).unwrap_or(false);
+        let declared = table.get("fields").and_then(|f| f.as_table()).ok_or("Schema has no [fields]")?;
+        let mut fields: Vec<FieldRule> = Vec::new();
+        for (key, spec) in declared.iter() {
+            let spec = spec.as_table().ok_or_else(|| format!("fields.{} is not a table", key))?;
+            let mut rule = FieldRule{key: key.clone(), required: true, validators: Vec::new()};
+            for (option, value) in spec.iter() {
+                let context = format!("fields.{}.{}", key, option);
+                match option.as_str() {
+                    "required" => rule.required = value.as_bool().ok_or(format!("{}: expected true or false", context))?,
+                    "range" => {
+                        let (low, high) = toml_range(value, &context)?;
+                        rule.validators.push(Validator::Range(low, high));
+                    },
+                    "units" => {
+                        let units = value.as_table().ok_or(format!("{}: expected a table of unit ranges", context))?;
+                        let units = units
+                            .iter()
+                            .map(|(unit, range)| toml_range(range, &context).map(|(low, high)| (unit.clone(), low, high)))
+                            .collect::<Result<Vec<_>, String>>()?;
+                        rule.validators.push(Validator::Units(units));
+                    },
+                    "pattern" => {
+                        let pattern = value.as_str().ok_or(format!("{}: expected a string", context))?;
+                        let re = Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| format!("{}: {}", context, e))?;
+                        rule.validators.push(Validator::Pattern(re, pattern.to_string()));
+                    },
+                    "one_of" => {
+                        let options = value
+                            .as_array()
+                            .and_then(|a| a.iter().map(|o| o.as_str().map(String::from)).collect::<Option<Vec<_>>>())
+                            .ok_or(format!("{}: expected a list of strings", context))?;
+                        rule.validators.push(Validator::OneOf(options));
+                    },
+                    _ => return Err(format!("{}: unknown option", context)),
+                }
+            }
+            fields.push(rule);
+        }
+        Ok(Schema{name, allow_unknown, fields})
+    }
+
+    fn aoc() -> Schema {
+        Schema::from_toml(AOC_SCHEMA).expect("Built-in schema is broken")
+    }
+
+    // Fields that are missing, unknown or fail a validator
+    fn violations(&self, record: &[(&str, &str)]) -> Vec<Violation> {
+        let mut violations: Vec<Violation> = Vec::new();
+        for rule in self.fields.iter() {
+            match record.iter().find(|(key, _)| *key == rule.key) {
+                None if rule.required => violations.push(Violation::new(&rule.key, "missing", "missing".to_string())),
+                None => (),
+                Some((_, value)) => {
+                    if let Some((kind, detail)) = rule.validators.iter().find_map(|v| v.check(value).err()) {
+                        violations.push(Violation::new(&rule.key, kind, detail));
                     }
-                    0
                 },
             }
         }
+        if !self.allow_unknown {
+            for (key, _) in record.iter().filter(|(key, _)| self.fields.iter().all(|f| f.key != *key)) {
+                violations.push(Violation::new(key, "unknown", "not in schema".to_string()));
+            }
+        }
+        violations
+    }
+
+    fn is_complete(&self, record: &[(&str, &str)]) -> bool {
+        self.fields
+            .iter()
+            .filter(|f| f.required)
+            .all(|f| record.iter().any(|(key, _)| *key == f.key))
     }
 }
 
-fn validate_height(
-    idx: Option<usize>,
-    data: &Vec<&str>,
-) -> usize {
-    match idx {
-        Some(i) => {
-            let field = data[i];
-            if field.ends_with("cm") {
-                match field_value_numeric(field.strip_suffix("cm").unwrap()) {
-                    Some(val) => {
-                        if val >= 150 && val <= 193 {
-                            return 1
-                        }
-                        0
-                    },
-                    None => 0,
-                }
-            } else if field.ends_with("in") {
-                match field_value_numeric(field.strip_suffix("in").unwrap()) {
-                    Some(val) => {
-                        if val >= 59 && val <= 76 {
-                            return 1
-                        }
-                        0
-                    },
-                    None => 0,
-                }
-            } else {
-                return 0
-            }
-            
+// Key/value pairs of one blank-line separated record
+fn parse_record(text: &str) -> Vec<(&str, &str)> {
+    text.split_whitespace()
+        .map(|word| match word.find(':') {
+            Some(idx) => (&word[..idx], &word[idx + 1..]),
+            None => (word, ""),
+        })
+        .collect()
+}
+
+// One record checked against the schema, `line` being where it starts in the input
+#[derive(Debug)]
+struct Record {
+    id: usize,
+    line: usize,
+    fields: Vec<(String, String)>,
+    complete: bool,
+    violations: Vec<Violation>,
+}
+
+impl Record {
+    fn text(&self) -> String {
+        let pairs: Vec<String> = self.fields.iter().map(|(k, v)| format!("{}:{}", k, v)).collect();
+        pairs.join(" ")
+    }
+
+    fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    fn passes(&self, key: &str) -> bool {
+        self.fields.iter().any(|(k, _)| k == key) && self.violations.iter().all(|v| v.field != key)
+    }
+}
+
+fn check_records(data: &str, schema: &Schema) -> Vec<Record> {
+    let mut records: Vec<Record> = Vec::new();
+    let mut line = 1;
+    for text in data.split("\n\n") {
+        let skipped = text.len() - text.trim_start().len();
+        let start = line + text[..skipped].matches('\n').count();
+        line += text.matches('\n').count() + 2;
+        if text.trim().is_empty() {
+            continue;
         }
-        None => { return 0 }
+        let pairs = parse_record(text);
+        records.push(Record{
+            id: records.len() + 1,
+            line: start,
+            fields: pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            complete: schema.is_complete(&pairs),
+            violations: schema.violations(&pairs),
+        });
     }
+    records
 }
 
-fn validate_regex(
-    idx: Option<usize>,
-    data: &Vec<&str>,
-    re: &Regex,
-) -> usize {
-    match idx {
-        None => 0,
-        Some(i) => {
-             re.is_match(data[i]) as usize
+fn print_report(records: &[Record], name: &str) {
+    for record in records.iter().filter(|r| !r.is_valid()) {
+        let violations: Vec<String> = record.violations.iter().map(|v| v.to_string()).collect();
+        println!("{} {} (line {}): {}", name, record.id, record.line, violations.join(", "));
+    }
+}
+
+// How often each kind of violation hit each field, most frequent first
+fn violation_summary(records: &[Record]) -> Vec<(String, &'static str, usize)> {
+    let mut counts: Vec<(String, &'static str, usize)> = Vec::new();
+    for violation in records.iter().flat_map(|r| r.violations.iter()) {
+        match counts.iter_mut().find(|(field, kind, _)| *field == violation.field && *kind == violation.kind) {
+            Some(entry) => entry.2 += 1,
+            None => counts.push((violation.field.clone(), violation.kind, 1)),
         }
     }
+    counts.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)).then(a.1.cmp(b.1)));
+    counts
+}
+
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
 }
 
-fn validate_ecl(
-    idx: Option<usize>,
-    data: &Vec<&str>,
-    hcls: &[&str; 7],
-) -> usize {
-    match idx {
-        None => 0,
-        Some(i) => {
-            let (_, ln) = data[i].split_at(4);
-            hcls.iter().any(|v| &ln == v ) as usize
+fn records_to_csv(records: &[Record]) -> String {
+    let mut lines = vec!["record,line,valid,field,kind,detail".to_string()];
+    for record in records {
+        if record.is_valid() {
+            lines.push(format!("{},{},true,,,", record.id, record.line));
+        }
+        for violation in record.violations.iter() {
+            lines.push(format!(
+                "{},{},false,{},{},{}",
+                record.id,
+                record.line,
+                csv_field(&violation.field),
+                violation.kind,
+                csv_field(&violation.detail),
+            ));
         }
     }
+    lines.join("\n") + "\n"
+}
+
+fn summary_to_csv(summary: &[(String, &'static str, usize)]) -> String {
+    let mut lines = vec!["field,kind,count".to_string()];
+    for (field, kind, count) in summary {
+        lines.push(format!("{},{},{}", csv_field(field), kind, count));
+    }
+    lines.join("\n") + "\n"
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HeightUnit {
+    Cm,
+    In,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Height {
+    value: u16,
+    unit: HeightUnit,
 }
 
-fn create_pass(
-    data: Vec<&str>,
-    re_hcl: &Regex,
-    re_pid: &Regex,
-    hcls: &[&str; 7],
-) -> (Pass, Pass) {
-    let byr = field_index(&data, "byr");
-    let iyr = field_index(&data, "iyr");
-    let eyr = field_index(&data, "eyr");
-    let hgt = field_index(&data, "hgt");
-    let hcl = field_index(&data, "hcl");
-    let ecl = field_index(&data, "ecl");
-    let pid = field_index(&data, "pid");
-    let cid = field_index(&data, "cid");
-    (Pass {
-        byr: is_field(&byr) as usize,
-        iyr: is_field(&iyr) as usize,
-        eyr: is_field(&eyr) as usize,
-        hgt: is_field(&hgt) as usize,
-        hcl: is_field(&hcl) as usize,
-        ecl: is_field(&ecl) as usize,
-        pid: is_field(&pid) as usize,
-        cid: is_field(&cid) as usize,
-    }, Pass {
-        byr: validate_numeric_range(byr, &data, 1920, 2002),
-        iyr: validate_numeric_range(iyr, &data, 2010, 2020),
-        eyr: validate_numeric_range(eyr, &data, 2020, 2030),
-        hgt: validate_height(hgt, &data),
-        hcl: validate_regex(hcl, &data, re_hcl),
-        ecl: validate_ecl(ecl, &data, hcls),
-        pid: validate_regex(pid, &data, re_pid),
-        cid: is_field(&cid) as usize,
-    })
+impl FromStr for Height {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Height, String> {
+        let (number, unit) = match (s.strip_suffix("cm"), s.strip_suffix("in")) {
+            (Some(number), _) => (number, HeightUnit::Cm),
+            (_, Some(number)) => (number, HeightUnit::In),
+            _ => return Err(format!("{} has no unit of cm in", s)),
+        };
+        let value = number.parse::<u16>().map_err(|_| format!("{} is not a height", s))?;
+        Ok(Height{value, unit})
+    }
+}
+
+impl fmt::Display for Height {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = match self.unit { HeightUnit::Cm => "cm", HeightUnit::In => "in" };
+        write!(f, "{}{}", self.value, unit)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rgb {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HairColor(Rgb);
+
+impl FromStr for HairColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HairColor, String> {
+        let hex = s
+            .strip_prefix('#')
+            .filter(|h| h.len() == 6 && h.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("{} is not #rrggbb", s))?;
+        let channel = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16).unwrap();
+        Ok(HairColor(Rgb{r: channel(0), g: channel(2), b: channel(4)}))
+    }
+}
+
+impl fmt::Display for HairColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0.r, self.0.g, self.0.b)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EyeColor {
+    Amb,
+    Blu,
+    Brn,
+    Gry,
+    Grn,
+    Hzl,
+    Oth,
+}
+
+const EYE_COLORS: [(EyeColor, &str); 7] = [
+    (EyeColor::Amb, "amb"),
+    (EyeColor::Blu, "blu"),
+    (EyeColor::Brn, "brn"),
+    (EyeColor::Gry, "gry"),
+    (EyeColor::Grn, "grn"),
+    (EyeColor::Hzl, "hzl"),
+    (EyeColor::Oth, "oth"),
+];
+
+impl FromStr for EyeColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EyeColor, String> {
+        EYE_COLORS
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(color, _)| *color)
+            .ok_or_else(|| format!("{} is not an eye color", s))
+    }
+}
+
+impl fmt::Display for EyeColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = EYE_COLORS.iter().find(|(color, _)| color == self).unwrap().1;
+        write!(f, "{}", name)
+    }
+}
+
+// Nine digits, leading zeros included
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PassportId(u32);
+
+impl FromStr for PassportId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PassportId, String> {
+        match s.len() == 9 && s.chars().all(|c| c.is_ascii_digit()) {
+            true => Ok(PassportId(s.parse().unwrap())),
+            false => Err(format!("{} is not a nine digit id", s)),
+        }
+    }
+}
+
+impl fmt::Display for PassportId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:09}", self.0)
+    }
+}
+
+// A passport with every field parsed; ranges are left to the schema
+#[derive(Debug, Clone, PartialEq)]
+struct Passport {
+    byr: u16,
+    iyr: u16,
+    eyr: u16,
+    hgt: Height,
+    hcl: HairColor,
+    ecl: EyeColor,
+    pid: PassportId,
+    cid: Option<u32>,
+}
+
+fn typed_field<T: FromStr>(record: &[(&str, &str)], key: &str) -> Result<Option<T>, String>
+where T::Err: fmt::Display {
+    match record.iter().find(|(k, _)| *k == key) {
+        Some((_, value)) => value.parse::<T>().map(Some).map_err(|e| format!("{}: {}", key, e)),
+        None => Ok(None),
+    }
+}
+
+impl FromStr for Passport {
+    type Err = String;
+
+    // One record in the batch format, fields separated by spaces or newlines
+    fn from_str(s: &str) -> Result<Passport, String> {
+        let record = parse_record(s);
+        fn required<T>(field: Option<T>, key: &str) -> Result<T, String> {
+            field.ok_or_else(|| format!("{}: missing", key))
+        }
+        Ok(Passport{
+            byr: required(typed_field(&record, "byr")?, "byr")?,
+            iyr: required(typed_field(&record, "iyr")?, "iyr")?,
+            eyr: required(typed_field(&record, "eyr")?, "eyr")?,
+            hgt: required(typed_field(&record, "hgt")?, "hgt")?,
+            hcl: required(typed_field(&record, "hcl")?, "hcl")?,
+            ecl: required(typed_field(&record, "ecl")?, "ecl")?,
+            pid: required(typed_field(&record, "pid")?, "pid")?,
+            cid: typed_field(&record, "cid")?,
+        })
+    }
+}
+
+impl fmt::Display for Passport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "byr:{} iyr:{} eyr:{} hgt:{} hcl:{} ecl:{} pid:{}",
+            self.byr, self.iyr, self.eyr, self.hgt, self.hcl, self.ecl, self.pid)?;
+        if let Some(cid) = self.cid {
+            write!(f, " cid:{}", cid)?;
+        }
+        Ok(())
+    }
+}
+
+fn to_batch(passports: &[Passport]) -> String {
+    passports.iter().map(|p| p.to_string() + "\n").collect::<Vec<String>>().join("\n")
 }
 
 fn load_demo() -> String {
@@ -222,31 +496,94 @@ iyr:2011 ecl:brn hgt:59in
 
 
 fn main() {
-    let mut totals = Pass{ byr: 0, ecl: 0, hcl: 0, hgt: 0, pid: 0, cid: 0, iyr: 0, eyr: 0};
-    let re_hcl = Regex::new(r"^hcl:#[0-9a-f]{6}$").unwrap();
-    let re_pid = Regex::new(r"^pid:\d{9}$").unwrap();
-    let ecls: [&str; 7] = ["amb", "blu", "brn", "gry", "grn", "hzl", "oth"];
-    let mut passports = 0;
-    let mut northpoles = 0;
-    let mut passports_strict = 0;
-    let mut northpoles_strict = 0;
-    // let data = load_demo();
-    let data = read_data();
-    for pass in data.split("\n\n") {
-        let one_line = pass.replace("\n", " ");
-        let words: Vec<&str> = one_line 
-            .split(" ")
-            .map(|w| w.trim())
+    let is_demo = false;
+    let verbose = false;
+    let export = false;
+    let rewrite = false;
+    // Arguments: [--schema PATH] [INPUT]
+    let mut schema = Schema::aoc();
+    let mut builtin = true;
+    let mut input = "./input.txt".to_string();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--schema" => {
+                let path = args.next().expect("Missing schema path");
+                schema = Schema::from_toml(&read_data(&path)).unwrap_or_else(|e| panic!("{}: {}", path, e));
+                builtin = false;
+            },
+            _ => input = arg,
+        }
+    }
+    let data = match is_demo { true => load_demo(), false => read_data(&input) };
+
+    let records = check_records(&data, &schema);
+    let summary = violation_summary(&records);
+    if verbose {
+        print_report(&records, &schema.name);
+        for (field, kind, count) in summary.iter() {
+            println!("{:>5} {}: {}", count, field, kind);
+        }
+    }
+    if export {
+        fs::write("./report.csv", records_to_csv(&records)).expect("Could not write report.csv");
+        fs::write("./violations.csv", summary_to_csv(&summary)).expect("Could not write violations.csv");
+    }
+
+    let complete = records.iter().filter(|r| r.complete).count();
+    let valid = records.iter().filter(|r| r.is_valid()).count();
+    println!("Part 1, complete {}s: {}", schema.name, complete);
+    println!("Part 2, valid {}s: {}", schema.name, valid);
+    let passes: Vec<String> = schema.fields
+        .iter()
+        .map(|rule| format!("{}: {}", rule.key, records.iter().filter(|r| r.passes(&rule.key)).count()))
+        .collect();
+    println!("Debug individual strict rule passes {}", passes.join(", "));
+
+    // Valid passports parsed into typed fields, which only model the built-in schema
+    if !builtin {
+        return;
+    }
+    let mut passports: Vec<Passport> = Vec::new();
+    for record in records.iter().filter(|r| r.is_valid()) {
+        match record.text().parse::<Passport>() {
+            Ok(passport) => passports.push(passport),
+            Err(e) => println!("passport {} (line {}) is valid but not typed: {}", record.id, record.line, e),
+        }
+    }
+    println!("Typed passports: {}", passports.len());
+    if rewrite {
+        fs::write("./passports.txt", to_batch(&passports)).expect("Could not write passports.txt");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passports_survive_a_batch_round_trip() {
+        let passports: Vec<Passport> = check_records(&load_demo(), &Schema::aoc())
+            .iter()
+            .filter(|r| r.is_valid())
+            .map(|r| r.text().parse::<Passport>().unwrap())
             .collect();
-        let (pass, strict_pass) = create_pass(words, &re_hcl, &re_pid, &ecls);
-        if pass.is_passport() { passports +=1; }
-        if pass.is_northpole_creds() { northpoles += 1; }
-        if strict_pass.is_passport() { passports_strict += 1}
-        if strict_pass.is_northpole_creds() { northpoles_strict += 1}
-        totals += strict_pass;
+        assert!(!passports.is_empty());
+        let reparsed: Vec<Passport> = to_batch(&passports)
+            .split("\n\n")
+            .map(|text| text.parse::<Passport>().unwrap())
+            .collect();
+        assert_eq!(reparsed, passports);
+    }
+
+    #[test]
+    fn empty_batch_is_empty() {
+        assert_eq!(to_batch(&[]), "");
     }
 
-    println!("Part 1, Pass: {} North Poles: {} Sum: {}", passports, northpoles, passports + northpoles);
-    println!("Part 2, Pass: {} North Poles: {} Sum: {}", passports_strict, northpoles_strict, passports_strict + northpoles_strict);
-    println!("Debug individual strict rule passes {:?}", totals);
+    #[test]
+    fn unparsable_cid_is_reported() {
+        let text = "byr:1980 iyr:2012 eyr:2025 hgt:170cm hcl:#123abc ecl:brn pid:000000001 cid:abc";
+        assert!(text.parse::<Passport>().unwrap_err().starts_with("cid:"));
+    }
 }
@@ -1,53 +1,120 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt;
 use std::fs;
 
-// 1023 is highest possible value by BBBBBBBRRR    
-const MAX_ID: usize = 1024;
+// How boarding passes partition a plane: row letters first, then column letters,
+// each pair being (lower half, upper half)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Layout {
+    row_bits: u32,
+    col_bits: u32,
+    row_letters: (char, char),
+    col_letters: (char, char),
+}
+
+impl Layout {
+    // 128 rows of 8 seats, BBBBBBBRRR being the highest id 1023
+    const AOC: Layout = Layout{row_bits: 7, col_bits: 3, row_letters: ('F', 'B'), col_letters: ('L', 'R')};
+
+    fn new(row_bits: u32, col_bits: u32, row_letters: (char, char), col_letters: (char, char)) -> Result<Layout, String> {
+        let letters = [row_letters.0, row_letters.1, col_letters.0, col_letters.1];
+        if row_bits + col_bits == 0 || row_bits + col_bits > 32 {
+            return Err(format!("{} row and {} column bits don't make a plane", row_bits, col_bits));
+        }
+        if (1..4).any(|i| letters[..i].contains(&letters[i])) {
+            return Err(format!("Letters {:?} are not distinct", letters));
+        }
+        Ok(Layout{row_bits, col_bits, row_letters, col_letters})
+    }
+
+    // ROW_BITS,COL_BITS,ROW_LETTERS,COL_LETTERS e.g. 7,3,FB,LR
+    fn from_spec(spec: &str) -> Result<Layout, String> {
+        let parts: Vec<&str> = spec.split(',').collect();
+        let bits = |s: &str| s.parse::<u32>().map_err(|e| format!("{}: {}", s, e));
+        let pair = |s: &str| match s.chars().collect::<Vec<char>>().as_slice() {
+            [low, high] => Ok((*low, *high)),
+            _ => Err(format!("{} is not two letters", s)),
+        };
+        match parts.as_slice() {
+            [rows, cols, row_letters, col_letters] =>
+                Layout::new(bits(rows)?, bits(cols)?, pair(row_letters)?, pair(col_letters)?),
+            _ => Err(format!("{} is not ROW_BITS,COL_BITS,ROW_LETTERS,COL_LETTERS", spec)),
+        }
+    }
 
-#[derive(Debug)]
+    fn seat_count(&self) -> usize {
+        1 << (self.row_bits + self.col_bits)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
 struct Seat {
     row: usize,
     col: usize,
+    layout: Layout,
+}
+
+// Reads `letters` as binary digits, the second letter of the pair being 1
+fn decode_bits(code: &str, letters: (char, char)) -> Option<usize> {
+    code.chars().try_fold(0, |value, chr| match chr {
+        _ if chr == letters.0 => Some(value << 1),
+        _ if chr == letters.1 => Some(value << 1 | 1),
+        _ => None,
+    })
+}
+
+fn encode_bits(value: usize, bits: u32, letters: (char, char)) -> String {
+    (0..bits)
+        .rev()
+        .map(|bit| match value >> bit & 1 { 0 => letters.0, _ => letters.1 })
+        .collect()
 }
 
 impl Seat {
     fn id(&self) -> usize {
-        self.row * 8 + self.col
+        self.row << self.layout.col_bits | self.col
     }
 
-    fn from_string(data: &str) -> Option<Self> {
-        let mut row: usize = 0;
-        let mut col: usize = 0;
-        let mut idx: usize = 0;
-        let base: usize = 2;
-        for chr in data.trim().chars() {
-            match (chr, idx) {
-                ('F', 0..=6) => {}, // Just ensuring it is valid
-                ('B', 0..=6) => {row += base.pow((6 - idx) as u32)},
-                ('L', 7..=9) => {}, // Just ensuring it is valid
-                ('R', 7..=9) => {col += base.pow( (9 - idx) as u32)},
-                _ => return None
-            }
-            idx += 1;
+    fn from_string(data: &str, layout: &Layout) -> Option<Self> {
+        let data = data.trim();
+        // Check that it's not a truncated id string
+        if data.chars().count() != (layout.row_bits + layout.col_bits) as usize {
+            return None
         }
-
-        // Check that it's not truncated id string
-        if idx != 10 { return None }
-
-        Some(Seat {row, col})
+        let split = data.char_indices().nth(layout.row_bits as usize).map(|(idx, _)| idx).unwrap_or(data.len());
+        let row = decode_bits(&data[..split], layout.row_letters)?;
+        let col = decode_bits(&data[split..], layout.col_letters)?;
+        Some(Seat {row, col, layout: *layout})
     }
 
-    fn from_id(id: usize) -> Option<Self> {
-        match id {
-            0..=MAX_ID => {
-                let col = id % 8;
-                let row = (id - col) / 8;
-                Some(Seat {row, col})
+    fn from_id(id: usize, layout: &Layout) -> Option<Self> {
+        match id < layout.seat_count() {
+            true => {
+                let col = id & ((1 << layout.col_bits) - 1);
+                let row = id >> layout.col_bits;
+                Some(Seat {row, col, layout: *layout})
             }
-            _ => None
+            false => None
         }
     }
 }
 
+// The boarding pass code, so `to_string` inverts `from_string`
+impl fmt::Display for Seat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}",
+            encode_bits(self.row, self.layout.row_bits, self.layout.row_letters),
+            encode_bits(self.col, self.layout.col_bits, self.layout.col_letters))
+    }
+}
+
+impl fmt::Debug for Seat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Seat").field("row", &self.row).field("col", &self.col).finish()
+    }
+}
+
 fn load_demo() -> String {
     r#"BFFFBBFRRR
 FFFBBBFRRR
@@ -62,18 +129,24 @@ fn load_data() -> String {
 
 
 fn main() {
-    let mut positions: [bool;MAX_ID] = [false; MAX_ID];
+    // Optional argument: a plane layout as ROW_BITS,COL_BITS,ROW_LETTERS,COL_LETTERS
+    let layout = match env::args().nth(1) {
+        Some(spec) => Layout::from_spec(&spec).unwrap_or_else(|e| panic!("{}", e)),
+        None => Layout::AOC,
+    };
+    // Occupied seat ids, sparse since a layout may have up to 2^32 seats
+    let mut positions: BTreeSet<usize> = BTreeSet::new();
     // let data = load_demo();
     let data = load_data();
     let mut highest_id = 0;
     for line in data.lines() {
-        match Seat::from_string(line) {
+        match Seat::from_string(line, &layout) {
             Some(seat) => {
                 println!("{:?}: {}", seat, seat.id());
                 if seat.id() > highest_id {
                     highest_id = seat.id();
                 }
-                positions[seat.id()] = true;
+                positions.insert(seat.id());
             },
             None => {println!("Skipped row")},
         }
@@ -83,22 +156,45 @@ fn main() {
     println!("\n*** Highest id {}\n", highest_id);
 
     // Debug from ID (bonus)
-    println!("Validate from ID {:?}", Seat::from_id(76));
-    println!("Validate from ID {:?}", Seat::from_id(518));
-    println!("Validate from ID {:?}", Seat::from_id(905));
+    println!("Validate from ID {:?}", Seat::from_id(76, &layout));
+    println!("Validate from ID {:?}", Seat::from_id(518, &layout));
+    println!("Validate from ID {:?}", Seat::from_id(905, &layout));
 
     // Part 2 answer
-    let pattern: [bool; 3] = [true, false, true];
-    let mut pidx = 0;
-    for idx in 0..MAX_ID {
-        if positions[idx] == pattern[pidx] {
-            pidx += 1;
-            if pidx == 3 {
-                println!("\n*** Found seat id {} @ {:?}\n", idx - 1, Seat::from_id(idx - 1));
-                break;
-            }
-        } else {
-            pidx = 0;
+    // A free seat with both neighbours taken lies in a gap of one between consecutive ids
+    let occupied: Vec<usize> = positions.into_iter().collect();
+    if let Some(pair) = occupied.windows(2).find(|pair| pair[1] == pair[0] + 2) {
+        println!("\n*** Found seat id {} @ {:?}\n", pair[0] + 1, Seat::from_id(pair[0] + 1, &layout));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(layout: &Layout) {
+        let ids = [0, 1, layout.seat_count() / 3, layout.seat_count() - 1];
+        for id in ids {
+            let seat = Seat::from_id(id, layout).unwrap();
+            assert_eq!(seat.id(), id);
+            assert_eq!(Seat::from_string(&seat.to_string(), layout), Some(seat), "{}", seat);
+        }
+        assert_eq!(Seat::from_id(layout.seat_count(), layout), None);
+    }
+
+    #[test]
+    fn aoc_passes_round_trip() {
+        assert_round_trip(&Layout::AOC);
+        for line in load_demo().lines().filter(|l| !l.trim().is_empty()) {
+            assert_eq!(Seat::from_string(line, &Layout::AOC).unwrap().to_string(), line);
         }
+        assert_eq!(Seat::from_string("BBBBBBBRRR", &Layout::AOC).unwrap().id(), 1023);
+    }
+
+    #[test]
+    fn custom_layouts_round_trip() {
+        assert_round_trip(&Layout::from_spec("4,6,UD,<>").unwrap());
+        assert_round_trip(&Layout::from_spec("0,5,FB,LR").unwrap());
+        assert_round_trip(&Layout::from_spec("16,16,FB,LR").unwrap());
     }
 }
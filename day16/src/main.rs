@@ -1,6 +1,7 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::fs;
 use regex::Regex;
+use constraints::{Problem, Solution};
 
 fn load_demo() -> String {
     r#"class: 1-3 or 5-7
@@ -35,63 +36,226 @@ fn load_data() -> String {
     fs::read_to_string("./input.txt").unwrap()
 }
 
+type Ranges = Vec<(usize, usize)>;
+
+fn union(a: &[(usize, usize)], b: &[(usize, usize)]) -> Ranges {
+    let mut all: Ranges = a.iter().chain(b.iter()).cloned().collect();
+    all.sort();
+    let mut merged: Ranges = vec![];
+    for (lb, ub) in all {
+        match merged.last_mut() {
+            Some(last) if lb <= last.1.saturating_add(1) => last.1 = last.1.max(ub),
+            _ => merged.push((lb, ub)),
+        }
+    }
+    merged
+}
+
+fn intersect(a: &Ranges, b: &Ranges) -> Ranges {
+    let mut out: Ranges = vec![];
+    for (lb_a, ub_a) in a {
+        for (lb_b, ub_b) in b {
+            let lb = *lb_a.max(lb_b);
+            let ub = *ub_a.min(ub_b);
+            if lb <= ub {
+                out.push((lb, ub));
+            }
+        }
+    }
+    union(&out, &[])
+}
+
+fn complement(a: &Ranges) -> Ranges {
+    let mut out: Ranges = vec![];
+    let mut next = Some(0);
+    for (lb, ub) in a {
+        if let Some(start) = next {
+            if *lb > start {
+                out.push((start, lb - 1));
+            }
+        }
+        next = ub.checked_add(1);
+    }
+    if let Some(start) = next {
+        out.push((start, usize::MAX));
+    }
+    out
+}
+
+// Rule expressions: ranges like `1-3`, `10-` or `-5` combined with `or`, `and`, `not` and parentheses
+struct ExprParser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn parse(text: &'a str) -> Result<Ranges, String> {
+        let tokens: Vec<&str> = text
+            .split_whitespace()
+            .flat_map(|t| split_parens(t))
+            .collect();
+        let mut parser = ExprParser{tokens, pos: 0};
+        let ranges = parser.expr()?;
+        match parser.tokens.get(parser.pos) {
+            Some(t) => Err(format!("Unexpected '{}' in '{}'", t, text)),
+            None => Ok(ranges),
+        }
+    }
+
+    fn next_is(&mut self, token: &str) -> bool {
+        if self.tokens.get(self.pos) == Some(&token) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expr(&mut self) -> Result<Ranges, String> {
+        let mut ranges = self.term()?;
+        while self.next_is("or") {
+            ranges = union(&ranges, &self.term()?);
+        }
+        Ok(ranges)
+    }
+
+    fn term(&mut self) -> Result<Ranges, String> {
+        let mut ranges = self.factor()?;
+        while self.next_is("and") {
+            ranges = intersect(&ranges, &self.factor()?);
+        }
+        Ok(ranges)
+    }
+
+    fn factor(&mut self) -> Result<Ranges, String> {
+        if self.next_is("not") {
+            return Ok(complement(&self.factor()?));
+        }
+        if self.next_is("(") {
+            let ranges = self.expr()?;
+            if !self.next_is(")") {
+                return Err("Missing ')'".to_string());
+            }
+            return Ok(ranges);
+        }
+        let token = self.tokens.get(self.pos).ok_or("Expected a range")?;
+        self.pos += 1;
+        let bound = |v: &str, open: usize| match v.is_empty() {
+            true => Ok(open),
+            false => v.parse::<usize>().map_err(|_| format!("Bad bound '{}'", v)),
+        };
+        match token.split_once('-') {
+            Some(("", "")) => Err("Range needs at least one bound".to_string()),
+            Some((lb, ub)) => match (bound(lb, 0)?, bound(ub, usize::MAX)?) {
+                (lb, ub) if lb > ub => Err(format!("Empty range '{}'", token)),
+                range => Ok(vec![range]),
+            },
+            None => bound(token, 0).map(|v| vec![(v, v)]),
+        }
+    }
+}
+
+fn split_parens(token: &str) -> Vec<&str> {
+    let mut parts: Vec<&str> = vec![];
+    let mut start = 0;
+    for (idx, c) in token.char_indices() {
+        if c == '(' || c == ')' {
+            if idx > start {
+                parts.push(&token[start..idx]);
+            }
+            parts.push(&token[idx..idx + 1]);
+            start = idx + 1;
+        }
+    }
+    if start < token.len() {
+        parts.push(&token[start..]);
+    }
+    parts
+}
+
 #[derive(Debug)]
 struct Rule {
     name: String,
-    lb_a: usize,
-    ub_a: usize,
-    lb_b: usize,
-    ub_b: usize,
+    ranges: Ranges,
 }
 
 impl Rule {
-    fn from_string(line: &str) -> Option<Self> {
-        let re = Regex::new(r"(.+): (\d+)-(\d+) or (\d+)-(\d+)").unwrap();
+    fn from_string(line: &str, re: &Regex) -> Result<Option<Self>, String> {
         match re.captures(line) {
             Some(caps) => {
-                let lb_a: usize = caps[2].parse::<usize>().unwrap();
-                let ub_a: usize = caps[3].parse::<usize>().unwrap();
-                let lb_b: usize = caps[4].parse::<usize>().unwrap();
-                let ub_b: usize = caps[5].parse::<usize>().unwrap();
-                Some(Rule{
-                    name: caps[1].to_string(),
-                    lb_a,
-                    ub_a,
-                    lb_b,
-                    ub_b,
-                })
+                let ranges = ExprParser::parse(&caps[2])?;
+                Ok(Some(Rule{name: caps[1].to_string(), ranges}))
             },
-            None => None,
+            None => Ok(None),
         }
     }
 
     fn matches(&self, value: &usize) -> bool {
-        *value >= self.lb_a && *value <= self.ub_a || *value >= self.lb_b && *value <= self.ub_b
+        let idx = self.ranges.partition_point(|(lb, _)| lb <= value);
+        idx > 0 && *value <= self.ranges[idx - 1].1
+    }
+}
+
+// Splits the number line at every rule bound so each segment knows which rules cover it
+struct RuleIndex {
+    starts: Vec<usize>,
+    rules: Vec<Vec<usize>>,
+}
+
+impl RuleIndex {
+    fn new(rules: &[Rule]) -> RuleIndex {
+        let mut bounds: Vec<usize> = vec![0];
+        for rule in rules {
+            for (lb, ub) in rule.ranges.iter() {
+                bounds.push(*lb);
+                if let Some(after) = ub.checked_add(1) {
+                    bounds.push(after);
+                }
+            }
+        }
+        bounds.sort();
+        bounds.dedup();
+        let segments: Vec<Vec<usize>> = bounds
+            .iter()
+            .map(|start| (0..rules.len()).filter(|id_rule| rules[*id_rule].matches(start)).collect())
+            .collect();
+        RuleIndex{starts: bounds, rules: segments}
+    }
+
+    fn matching(&self, value: &usize) -> &[usize] {
+        let idx = self.starts.partition_point(|start| start <= value);
+        &self.rules[idx - 1]
+    }
+
+    fn any(&self, value: &usize) -> bool {
+        !self.matching(value).is_empty()
     }
 }
 
-fn to_numbers(line: &str) -> Vec<usize> {
+fn to_numbers(line: &str) -> Result<Vec<usize>, String> {
     line
         .split(",")
-        .map(|v| v.trim().parse().unwrap())
+        .map(|v| v.trim().parse().map_err(|_| format!("Bad number '{}'", v)))
         .collect()
 }
+
 #[derive(Debug)]
 enum ParsePhase {Rules, OwnHeader, Own, NearbyHeader, Nearby}
 
-fn parse(data: String) -> (
-    Vec<Rule>,
-    Vec<usize>,
-    Vec<Vec<usize>>,
-) {
+type Notes = (Vec<Rule>, Vec<usize>, Vec<Vec<usize>>);
+
+fn parse(data: String) -> Result<Notes, String> {
     let mut phase = ParsePhase::Rules;
     let mut rules: Vec<Rule> = vec![];
     let mut own: Vec<usize> = vec![];
     let mut nearby: Vec<Vec<usize>> = vec![];
-    for line in data.lines() {
+    let re = Regex::new(r"^([^:]+): (.+)$").unwrap();
+    for (id_line, line) in data.lines().enumerate() {
+        let unexpected = |phase: &ParsePhase, msg: &str| format!(
+            "Line {} ({:?}): {}: {}", id_line + 1, phase, msg, line,
+        );
         match phase {
             ParsePhase::Rules => {
-                phase = match Rule::from_string(line) {
+                phase = match Rule::from_string(line, &re).map_err(|msg| unexpected(&phase, &msg))? {
                     Some(rule) => {
                         rules.push(rule);
                         ParsePhase::Rules
@@ -101,122 +265,241 @@ fn parse(data: String) -> (
             },
             ParsePhase::OwnHeader => {
                 if !line.starts_with("your ticket:") {
-                    panic!("Unexpected line ({:?}): {}", phase, line);
+                    return Err(unexpected(&phase, "Expected 'your ticket:'"));
                 }
                 phase = ParsePhase::Own;
             }
             ParsePhase::Own => {
-                if own.len() == 0 {
-                    own.append(&mut to_numbers(line));
-                } else if line.len() > 0 {
-                    panic!("Unexpected line ({:?}): {}", phase, line);
+                if own.is_empty() {
+                    own = to_numbers(line).map_err(|msg| unexpected(&phase, &msg))?;
+                } else if !line.is_empty() {
+                    return Err(unexpected(&phase, "Expected a single ticket"));
                 } else {
                     phase = ParsePhase::NearbyHeader;
                 }
             }
             ParsePhase::NearbyHeader => {
                 if !line.starts_with("nearby tickets:") {
-                    panic!("Unexpected line ({:?}): {}", phase, line);
+                    return Err(unexpected(&phase, "Expected 'nearby tickets:'"));
                 }
                 phase = ParsePhase::Nearby                
             }    
             ParsePhase::Nearby => {
-                nearby.push(to_numbers(line));
+                if line.is_empty() { continue; }
+                let ticket = to_numbers(line).map_err(|msg| unexpected(&phase, &msg))?;
+                if ticket.len() != rules.len() {
+                    let msg = format!("Ticket has {} fields but there are {} rules", ticket.len(), rules.len());
+                    return Err(unexpected(&phase, &msg));
+                }
+                nearby.push(ticket);
             }
         }
     }
-    // println!("{:?}", rules);
-    // println!("My {:?}", own);
-    // println!("Nearby:\n{:?}", nearby);
-    (rules, own, nearby)
+    if own.len() != rules.len() {
+        return Err(format!("Own ticket has {} fields but there are {} rules", own.len(), rules.len()));
+    }
+    Ok((rules, own, nearby))
+}
+
+// For every position the solutions disagree on, a value that would rule out each candidate rule
+fn disambiguations(
+    assignments: &[BTreeMap<usize, usize>],
+    rules: &[Rule],
+) -> Vec<(usize, usize, Option<usize>)> {
+    let mut hints: Vec<(usize, usize, Option<usize>)> = vec![];
+    for id_pos in assignments[0].keys() {
+        let mut candidates: Vec<usize> = assignments.iter().map(|a| a[id_pos]).collect();
+        candidates.sort();
+        candidates.dedup();
+        if candidates.len() < 2 { continue; }
+        for id_rule in candidates.iter() {
+            let others = candidates
+                .iter()
+                .filter(|other| *other != id_rule)
+                .fold(vec![], |acc, other| union(&acc, &rules[*other].ranges));
+            let witness = intersect(&others, &complement(&rules[*id_rule].ranges))
+                .first()
+                .map(|(lb, _)| *lb);
+            hints.push((*id_pos, *id_rule, witness));
+        }
+    }
+    hints
 }
 
 fn invalid_numbers(
-    rules: &Vec<Rule>,
+    index: &RuleIndex,
     ticket: &Vec<usize>,
 ) -> Vec<usize> {
     ticket
         .iter()
-        .filter(| v | !rules.iter().any(| r| r.matches(v)))
+        .filter(| v | !index.any(v))
         .map(|v| v.clone())
         .collect()
 }
 
-fn init_mapping(rules: &Vec<Rule>) -> HashMap<usize, Vec<usize>> {
+fn init_mapping(rules: &Vec<Rule>) -> Problem<usize, usize> {
     let l = rules.len();
-    let mut mapping = HashMap::new();
+    let mut mapping = Problem::new();
     for idx in 0..l {
-        mapping.insert(idx, (0..l).collect());
+        mapping.restrict(idx, 0..l);
     }
     mapping
 }
 
 fn update_mapping(
     ticket: &Vec<usize>,
-    mapping: &mut HashMap<usize, Vec<usize>>,
-    rules: &Vec<Rule>
+    mapping: &mut Problem<usize, usize>,
+    index: &RuleIndex,
 ) {
-    //println!("{:?}", mapping);
     for id_pos in 0..ticket.len() {
-        for id_rule in (0..mapping[&id_pos].len()).rev() {
-            // println!("{}->{} ({} in {:?} {})", id_pos, id_rule, near[id_pos], rules[id_rule], rules[id_rule].matches(&near[id_pos]));
-            if !rules[mapping[&id_pos][id_rule]].matches(&ticket[id_pos]) {
-                mapping.get_mut(&id_pos).unwrap().remove(id_rule);
-            }
-        }
+        mapping.restrict(id_pos, index.matching(&ticket[id_pos]).iter().cloned());
     }
-    //print_ticket(&ticket, &mapping, &rules);
-    while cleanup_mapping(mapping) {}
 }
 
-fn cleanup_mapping(
-    mapping: &mut HashMap<usize, Vec<usize>>,
-) -> bool {
-    let mut changed = false;
-    for id_pos in 0..mapping.len() {
-        if mapping[&id_pos].len() == 1 {
-            let val = mapping[&id_pos][0];
-            for id_pos2 in 0..mapping.len() {
-                if id_pos == id_pos2 {
-                    continue;
-                }
+#[derive(Debug)]
+struct FieldError {
+    position: usize,
+    value: usize,
+    nearest_rule: usize,
+    nearest_range: (usize, usize),
+}
 
-                let v = mapping.get_mut(&id_pos2).unwrap();
-                let before = v.len();
-                v.retain(|v| v != &val);
-                if !changed && before != v.len() {
-                    changed = true;
+impl FieldError {
+    fn distance(&self) -> usize {
+        let (lb, ub) = self.nearest_range;
+        match self.value < lb { true => lb - self.value, false => self.value - ub }
+    }
+}
+
+#[derive(Debug)]
+struct TicketReport {
+    ticket: usize,
+    errors: Vec<FieldError>,
+}
+
+fn validate_ticket(id_ticket: usize, ticket: &[usize], rules: &[Rule], index: &RuleIndex) -> TicketReport {
+    let mut errors: Vec<FieldError> = vec![];
+    for (position, value) in ticket.iter().enumerate() {
+        if index.any(value) { continue; }
+        let mut nearest: Option<FieldError> = None;
+        for (id_rule, rule) in rules.iter().enumerate() {
+            for range in rule.ranges.iter() {
+                let candidate = FieldError{position, value: *value, nearest_rule: id_rule, nearest_range: *range};
+                if nearest.as_ref().is_none_or(|n| candidate.distance() < n.distance()) {
+                    nearest = Some(candidate);
                 }
             }
-        } 
+        }
+        if let Some(error) = nearest {
+            errors.push(error);
+        }
     }
-    changed
+    TicketReport{ticket: id_ticket, errors}
+}
+
+fn format_range((lb, ub): (usize, usize)) -> String {
+    match (lb, ub) {
+        (0, usize::MAX) => "any".to_string(),
+        (lb, usize::MAX) => format!("{}-", lb),
+        (0, ub) => format!("-{}", ub),
+        (lb, ub) => format!("{}-{}", lb, ub),
+    }
+}
+
+fn print_reports(reports: &[TicketReport], rules: &[Rule]) {
+    for report in reports.iter().filter(|r| !r.errors.is_empty()) {
+        println!("Nearby ticket {}:", report.ticket);
+        for error in report.errors.iter() {
+            println!(
+                "  position {} value {} matches no rule, nearest is {} {} (off by {})",
+                error.position,
+                error.value,
+                rules[error.nearest_rule].name,
+                format_range(error.nearest_range),
+                error.distance(),
+            );
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+fn reports_to_csv(reports: &[TicketReport], rules: &[Rule]) -> String {
+    let mut lines = vec!["ticket,position,value,nearest_rule,nearest_range,distance".to_string()];
+    for report in reports {
+        for error in report.errors.iter() {
+            lines.push(format!(
+                "{},{},{},{},{},{}",
+                report.ticket,
+                error.position,
+                error.value,
+                csv_field(&rules[error.nearest_rule].name),
+                format_range(error.nearest_range),
+                error.distance(),
+            ));
+        }
+    }
+    lines.join("\n") + "\n"
 }
 
 fn print_ticket(
-    ticket: &Vec<usize>,
-    mapping: &HashMap<usize, Vec<usize>>,
-    rules: &Vec<Rule>
+    ticket: &[usize],
+    assignment: &BTreeMap<usize, usize>,
+    rules: &[Rule]
 ) {
-    for id_pos in 0..ticket.len() {
-        for id_rule in mapping.get(&id_pos).unwrap() {
-            let rule = rules.get(*id_rule).unwrap();
-            print!("{}\t", rule.name);
-        }
-        println!("{}", ticket[id_pos]);
+    for (id_pos, id_rule) in assignment {
+        println!("{}\t{}", rules[*id_rule].name, ticket[*id_pos]);
     }
-    println!("");
+    println!();
+}
+
+fn ticket_to_csv(
+    ticket: &[usize],
+    assignment: &BTreeMap<usize, usize>,
+    rules: &[Rule]
+) -> String {
+    let mut lines = vec!["field,position,value".to_string()];
+    for (id_pos, id_rule) in assignment {
+        lines.push(format!("{},{},{}", csv_field(&rules[*id_rule].name), id_pos, ticket[*id_pos]));
+    }
+    lines.join("\n") + "\n"
 }
 
 fn main() {
     // let data = load_demo();
     // let data = load_demo2();
     let data = load_data();
-    let (rules, own, nearby) = parse(data);
+    let verbose = false;
+    let export = false;
+    let max_mappings = 100;
+    let (rules, own, nearby) = match parse(data) {
+        Ok(parsed) => parsed,
+        Err(msg) => {
+            println!("Could not parse input: {}", msg);
+            return;
+        },
+    };
+    let index = RuleIndex::new(&rules);
+    let reports: Vec<TicketReport> = nearby
+        .iter()
+        .enumerate()
+        .map(|(id_ticket, near)| validate_ticket(id_ticket, near, &rules, &index))
+        .collect();
+    if verbose {
+        print_reports(&reports, &rules);
+    }
+    if export {
+        fs::write("./nearby_report.csv", reports_to_csv(&reports, &rules)).expect("Could not write nearby_report.csv");
+    }
     let mut invalids: Vec<usize> = vec![];
     let mut valids: Vec<Vec<usize>> = vec![];
     for near in nearby {
-        let mut invalid_nums = invalid_numbers(&rules, &near);
+        let mut invalid_nums = invalid_numbers(&index, &near);
         if invalid_nums.is_empty() {
             valids.push(near);
         } else {
@@ -227,19 +510,47 @@ fn main() {
     println!("Part 1: {}", error_rate);
     // println!("Passing {:?}", valids);
     let mut mapping = init_mapping(&rules);
-    update_mapping(&own, &mut mapping, &rules);
+    update_mapping(&own, &mut mapping, &index);
     for near in valids {
-        update_mapping(&near, &mut mapping, &rules);
+        update_mapping(&near, &mut mapping, &index);
+    }
+    let assignment = match mapping.solve(max_mappings) {
+        Solution::Unique(assignment) => assignment,
+        Solution::Ambiguous(assignments) => {
+            println!("Ambiguous mapping, found {} possible assignments:", assignments.len());
+            for assignment in assignments.iter() {
+                println!("{:?}", assignment);
+            }
+            for (id_pos, id_rule, witness) in disambiguations(&assignments, &rules) {
+                match witness {
+                    Some(value) => println!(
+                        "A valid ticket with {} at position {} would rule out {}", value, id_pos, rules[id_rule].name,
+                    ),
+                    None => println!(
+                        "No ticket value at position {} can rule out {}", id_pos, rules[id_rule].name,
+                    ),
+                }
+            }
+            return;
+        },
+        Solution::Impossible(Some(id_pos)) => {
+            println!("No consistent mapping, no rule fits position {}", id_pos);
+            return;
+        },
+        Solution::Impossible(None) => {
+            println!("No consistent mapping, positions can't all get distinct rules");
+            return;
+        },
+    };
+    print_ticket(&own, &assignment, &rules);
+    if export {
+        fs::write("./ticket.csv", ticket_to_csv(&own, &assignment, &rules)).expect("Could not write ticket.csv");
     }
-    // print_ticket(&own, &mapping, &rules);
     let mut prod = 1;
-    for (id_pos, id_rules) in mapping {
-        if id_rules.len() != 1 { panic!("Ambiguity!")}
-        let id_rule = id_rules.first().unwrap();
-        if rules[*id_rule].name.starts_with("departure") {
+    for (id_pos, id_rule) in assignment {
+        if rules[id_rule].name.starts_with("departure") {
             prod *= own[id_pos];
         }
     }
     println!("Part 2: {}", prod);
 }
-
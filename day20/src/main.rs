@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 fn load_demo() -> String {
     r#"Tile 2311:
@@ -114,67 +114,61 @@ fn load_data() -> String {
     std::fs::read_to_string("./input.txt").unwrap()
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum Dir {West, WestFlipped, North, NorthFlipped, East, EastFlipped, South, SouthFlipped}
+// An element of the dihedral group D4: mirror left-right (if flipped), then rotate clockwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Orientation {
+    rotation: usize,
+    flipped: bool,
+}
 
-impl Dir {
-    fn is_flipped(&self) -> bool {
-        match self {
-            Dir::West | Dir::North | Dir::South | Dir::East => false,
-            _ => true,
-        }
+impl Orientation {
+    #[cfg(test)]
+    const IDENTITY: Orientation = Orientation{rotation: 0, flipped: false};
+
+    fn all() -> Vec<Orientation> {
+        [false, true]
+            .iter()
+            .flat_map(|flipped| (0..4).map(move |rotation| Orientation{rotation, flipped: *flipped}))
+            .collect()
     }
 
-    fn to_ordinal(&self) -> usize {
-        match self {
-            Dir::North | Dir::NorthFlipped => 0,
-            Dir::East | Dir::EastFlipped  => 1,
-            Dir::South | Dir::SouthFlipped => 2,
-            Dir::West | Dir::WestFlipped => 3,
+    // Applying self and then next
+    fn then(&self, next: &Orientation) -> Orientation {
+        match next.flipped {
+            true => Orientation{rotation: (4 + next.rotation - self.rotation) % 4, flipped: !self.flipped},
+            false => Orientation{rotation: (self.rotation + next.rotation) % 4, flipped: self.flipped},
         }
     }
 
-    fn opposing_ordinal(val: &usize) -> usize {
-        match val {
-            0 => 2,
-            1 => 3,
-            2 => 0,
-            3 => 1,
-            _ => panic!("Unknown ordinal {}", val),
+    #[cfg(test)]
+    fn inverse(&self) -> Orientation {
+        match self.flipped {
+            true => *self,
+            false => Orientation{rotation: (4 - self.rotation) % 4, flipped: false},
         }
     }
-    
-    fn ortho_flip(&self, other: &Dir) -> bool {
-        match self {
-            Dir::East | Dir::EastFlipped => *other == Dir::East || *other == Dir::EastFlipped,
-            Dir::West | Dir::WestFlipped => *other == Dir::West || *other == Dir::WestFlipped,
-            Dir::South | Dir::SouthFlipped => *other == Dir::South || *other == Dir::SouthFlipped,
-            Dir::North | Dir::NorthFlipped => *other == Dir::North|| *other == Dir::NorthFlipped,
+
+    fn apply_to_grid(&self, grid: &[String]) -> Vec<String> {
+        let mut out = match self.flipped { true => flip_grid(grid), false => grid.to_vec() };
+        for _ in 0..self.rotation {
+            out = rotate_grid(&out);
         }
+        out
     }
-}
-
-#[derive(Debug, Clone)]
-struct Edge {    
-    target: usize,
-    from: Dir,
-    to: Dir,
-}
 
-impl Edge {
-    fn is_ortho_flipping(&self) -> bool {
-        self.from.ortho_flip(&self.to)
+    // Where a cell of a square grid of the given size ends up
+    fn apply_to_position(&self, row: usize, col: usize, size: usize) -> (usize, usize) {
+        let mut pos = match self.flipped { true => (row, size - 1 - col), false => (row, col) };
+        for _ in 0..self.rotation {
+            pos = (pos.1, size - 1 - pos.0);
+        }
+        pos
     }
 
-    fn secondary_direction_numeral(&self) -> usize {
-        // println!("{:?} {} {}", self, self.from.to_ordinal(), self.to.to_ordinal());
-        match (self.from.to_ordinal(), self.to.to_ordinal()) {
-            (3, 1) => 0,
-            (1, 3) => 2,
-            (0, 2) => 1,
-            (2, 0) => 3,
-            _ => panic!("No secondary dir for {:?}", self),
-        }
+    // Where a side (0 North, 1 East, 2 South, 3 West) ends up
+    fn apply_to_edge(&self, side: usize) -> usize {
+        let mirrored = match self.flipped { true => (4 - side) % 4, false => side };
+        (mirrored + self.rotation) % 4
     }
 }
 
@@ -182,67 +176,14 @@ impl Edge {
 struct Tile {
     id: usize,
     tile: Vec<String>,
-    sides: HashMap<String, Vec<Dir>>,    
-    edges: Vec<Vec<Edge>>,
+    sides: HashMap<String, Vec<Orientation>>,
 }
 
-fn sides_from_tile(tile: &Vec<String>) -> HashMap<String, Vec<Dir>> {
-    let mut sides: HashMap<String, Vec<Dir>> = HashMap::new();
-    // North
-    let north: String = tile[0].clone();
-    let inv_north = north.chars().rev().collect::<String>();
-    sides.insert(north, vec![Dir::North]);
-    if sides.contains_key(&inv_north) {
-        sides.get_mut(&inv_north).unwrap().push(Dir::NorthFlipped);
-    } else {
-        sides.insert(inv_north, vec![Dir::NorthFlipped]);
-    }
-    // South
-    let south: String = tile[tile.len() - 1].clone();
-    let inv_south= south.chars().rev().collect::<String>();
-    if sides.contains_key(&south) {
-        sides.get_mut(&south).unwrap().push(Dir::South);
-    } else {
-        sides.insert(south, vec![Dir::South]);
-    }
-    if sides.contains_key(&inv_south) {
-        sides.get_mut(&inv_south).unwrap().push(Dir::SouthFlipped);
-    } else {
-        sides.insert(inv_south, vec![Dir::SouthFlipped]);
-    }
-    // West - East collecting
-    let mut west_chars: Vec<char> = vec![];
-    let mut east_chars: Vec<char> = vec![];
-    for line in tile {
-        let chars: Vec<char> = line.chars().collect();
-        west_chars.push(chars.first().unwrap().clone());
-        east_chars.push(chars.last().unwrap().clone());
-    }
-    // West
-    let west: String = west_chars.iter().collect();
-    let inv_west: String = west_chars.into_iter().rev().collect();
-    if sides.contains_key(&west) {
-        sides.get_mut(&west).unwrap().push(Dir::West);
-    } else {
-        sides.insert(west, vec![Dir::West]);
-    }
-    if sides.contains_key(&inv_west) {
-        sides.get_mut(&inv_west).unwrap().push(Dir::WestFlipped);
-    } else {
-        sides.insert(inv_west, vec![Dir::WestFlipped]);
-    }
-    // East
-    let east: String = east_chars.iter().collect();
-    let inv_east: String = east_chars.into_iter().rev().collect();
-    if sides.contains_key(&east) {
-        sides.get_mut(&east).unwrap().push(Dir::East);
-    } else {
-        sides.insert(east, vec![Dir::East]);
-    }
-    if sides.contains_key(&inv_east) {
-        sides.get_mut(&inv_east).unwrap().push(Dir::EastFlipped);
-    } else {
-        sides.insert(inv_east, vec![Dir::EastFlipped]);
+fn sides_from_tile(tile: &[String]) -> HashMap<String, Vec<Orientation>> {
+    let mut sides: HashMap<String, Vec<Orientation>> = HashMap::new();
+    for orientation in Orientation::all() {
+        let north = orientation.apply_to_grid(tile)[0].clone();
+        sides.entry(north).or_default().push(orientation);
     }
     sides
 }
@@ -258,8 +199,7 @@ impl Tile {
             match lines.next() {
                 Some(line) => { if line.trim().is_empty() {
                         let sides = sides_from_tile(&tile);
-                        let edges: Vec<Vec<Edge>> = vec![vec![], vec![], vec![], vec![]];
-                        tiles.push(Tile{id, tile, sides, edges});
+                        tiles.push(Tile{id, tile, sides});
                         id = 0;
                         tile = vec![];
                     } else if line.starts_with("Tile") {
@@ -275,335 +215,432 @@ impl Tile {
                 },
                 None => {
                     let sides = sides_from_tile(&tile);
-                    let edges: Vec<Vec<Edge>> = vec![vec![], vec![], vec![], vec![]];
-                    tiles.push(Tile{id, tile, sides, edges});
+                    tiles.push(Tile{id, tile, sides});
                     return tiles;
                 }
             }
         }
     }
+}
 
-    fn possible_connections(&self, other: &Tile) -> Vec<(Dir, Dir)> {
-        let mut pairs:  Vec<(Dir, Dir)> = vec![];
-        for (k, edges) in self.sides.iter() {
-            if other.sides.contains_key(k) {
-                for self_e in edges {
-                    for other_e in other.sides.get(k).unwrap() {
-                        pairs.push(((*self_e).clone(), (*other_e).clone()));
-                    }
-                }
-            }
-        }
-        pairs
-    }
+struct Image {
+    tiles: HashMap<usize, Tile>,
+}
 
-    fn register_connections(&mut self, other_id: usize, connections: &Vec<(Dir, Dir)>) {
-        for (from, to) in connections.iter() {
-            match from {
-                Dir::North | Dir::NorthFlipped => {
-                    self.edges[0].push(Edge{target: other_id, from: from.clone(), to: to.clone()});
-                }
-                Dir::East | Dir::EastFlipped => {
-                    self.edges[1].push(Edge{target: other_id, from: from.clone(), to: to.clone()});
-                },
-                Dir::South | Dir::SouthFlipped => {
-                    self.edges[2].push(Edge{target: other_id, from: from.clone(), to: to.clone()});
-                },
-                Dir::West | Dir::WestFlipped => {
-                    self.edges[3].push(Edge{target: other_id, from: from.clone(), to: to.clone()});
-                }
+fn rotate_grid(grid: &[String]) -> Vec<String> {
+    let chars: Vec<Vec<char>> = grid.iter().map(|l| l.chars().collect()).collect();
+    let size = chars.len();
+    (0..chars[0].len())
+        .map(|col| (0..size).rev().map(|row| chars[row][col]).collect())
+        .collect()
+}
+
+fn flip_grid(grid: &[String]) -> Vec<String> {
+    grid.iter().map(|l| l.chars().rev().collect()).collect()
+}
+
+fn west_side(grid: &[String]) -> String {
+    grid.iter().map(|l| l.chars().next().unwrap()).collect()
+}
+
+fn east_side(grid: &[String]) -> String {
+    grid.iter().map(|l| l.chars().last().unwrap()).collect()
+}
+
+#[derive(Debug, Clone)]
+struct Placement {
+    id: usize,
+    orientation: Orientation,
+    tile: Vec<String>,
+}
+
+struct Solver<'a> {
+    image: &'a Image,
+    size: usize,
+    variants: HashMap<usize, Vec<(Orientation, Vec<String>)>>,
+    used: HashSet<usize>,
+    slots: Vec<Placement>,
+}
+
+impl<'a> Solver<'a> {
+    fn new(image: &'a Image) -> Solver<'a> {
+        let size = (image.tiles.len() as f64).sqrt().round() as usize;
+        let variants = image.tiles
+            .iter()
+            .map(|(id, t)| (*id, Orientation::all().into_iter().map(|o| (o, o.apply_to_grid(&t.tile))).collect()))
+            .collect();
+        Solver{image, size, variants, used: HashSet::new(), slots: vec![]}
+    }
+
+    fn side_matches(&self, id: usize, side: &str) -> usize {
+        self.image.tiles.values().filter(|t| t.id != id && t.sides.contains_key(side)).count()
+    }
+
+    fn candidates(&self, west: Option<&String>, north: Option<&String>) -> Vec<(usize, usize)> {
+        let mut candidates: Vec<(usize, usize, usize)> = vec![];
+        for tile in self.image.tiles.values() {
+            if self.used.contains(&tile.id) { continue; }
+            if west.is_some_and(|w| !tile.sides.contains_key(w)) { continue; }
+            if north.is_some_and(|n| !tile.sides.contains_key(n)) { continue; }
+            for (idx, (_, variant)) in self.variants[&tile.id].iter().enumerate() {
+                if west.is_some_and(|w| west_side(variant) != *w) { continue; }
+                if north.is_some_and(|n| variant[0] != *n) { continue; }
+                let rank = match west.or(north) {
+                    Some(_) => 0,
+                    None => self.side_matches(tile.id, &variant[0]) + self.side_matches(tile.id, &west_side(variant)),
+                };
+                candidates.push((rank, tile.id, idx));
             }
         }
+        candidates.sort();
+        candidates.into_iter().map(|(_, id, idx)| (id, idx)).collect()
     }
 
-    fn register_connections_rev(&mut self, other_id: usize, connections: &Vec<(Dir, Dir)>) {
-        for (from, to) in connections.iter() {
-            match to {
-                Dir::North | Dir::NorthFlipped => {
-                    self.edges[0].push(Edge{target: other_id, from: to.clone(), to: from.clone()});
-                }
-                Dir::East | Dir::EastFlipped => {
-                    self.edges[1].push(Edge{target: other_id, from: to.clone(), to: from.clone()});
-                },
-                Dir::South | Dir::SouthFlipped => {
-                    self.edges[2].push(Edge{target: other_id, from: to.clone(), to: from.clone()});
-                },
-                Dir::West | Dir::WestFlipped => {
-                    self.edges[3].push(Edge{target: other_id, from: to.clone(), to: from.clone()});
-                }
+    fn solve(&mut self) -> bool {
+        let slot = self.slots.len();
+        if slot == self.size * self.size {
+            return true;
+        }
+        let west = match slot % self.size { 0 => None, _ => Some(east_side(&self.slots[slot - 1].tile)) };
+        let north = match slot / self.size { 0 => None, _ => self.slots[slot - self.size].tile.last().cloned() };
+        for (id, idx) in self.candidates(west.as_ref(), north.as_ref()) {
+            self.used.insert(id);
+            let (orientation, tile) = self.variants[&id][idx].clone();
+            self.slots.push(Placement{id, orientation, tile});
+            if self.solve() {
+                return true;
             }
+            self.slots.pop();
+            self.used.remove(&id);
         }
+        false
     }
+}
 
-
-    fn unresolved_connections(&self) -> usize {
-        self
-            .edges
-            .iter()
-            .filter(|e| e.len() > 1)
-            .count()
+impl Image {
+    fn assemble(&self) -> Option<Vec<Vec<Placement>>> {
+        let mut solver = Solver::new(self);
+        if solver.size * solver.size != self.tiles.len() || !solver.solve() {
+            return None;
+        }
+        Some(solver.slots.chunks(solver.size).map(|row| row.to_vec()).collect())
     }
+}
 
-    fn connections(&self) -> usize {
-        self
-            .edges
-            .iter()
-            .filter(|e| e.len() > 0)
-            .count()
+fn stitch(layout: &[Vec<Placement>]) -> Vec<String> {
+    let mut image: Vec<String> = vec![];
+    for row in layout {
+        let inner = row[0].tile.len() - 1;
+        for line in 1..inner {
+            image.push(row
+                .iter()
+                .map(|p| p.tile[line][1..inner].to_string())
+                .collect());
+        }
     }
+    image
+}
 
-    fn north_west_corner(&self) -> bool {
-        self.edges[0].len() == 0 && self.edges[1].len() > 0 && self.edges[2].len() > 0 && self.edges[3].len() == 0
-    }
+fn load_sea_monster() -> String {
+    r#"                  # 
+#    ##    ##    ###
+ #  #  #  #  #  #   "#.to_string()
+}
 
-    fn prune_other_edges(&mut self, dir: Dir, target: usize) {
-        if self.id == 1427 {println!("Removing not {:?} to {}", dir, target)}
-        let i = dir.to_ordinal();
-        for j in (0..self.edges[i].len()).rev() {
-            let edge = self.edges.get(i).unwrap().get(j).unwrap();
-            if edge.target != target || dir != edge.from {
-                self.edges.get_mut(i).unwrap().remove(j);
-            }
-        }
-    }
+#[derive(Debug)]
+struct Pattern {
+    cells: Vec<(usize, usize)>,
+    height: usize,
+    width: usize,
+}
 
-    fn prune_edges(&mut self, first: &bool) {
-        if *first {
-            for i in 0..4 {
-                for j in (0..self.edges[i].len()).rev() {
-                    let edge = self.edges.get(i).unwrap().get(j).unwrap();
-                    if edge.from.is_flipped() {
-                        self.edges.get_mut(i).unwrap().remove(j);
-                    }
+impl Pattern {
+    fn from_string(data: &str) -> Pattern {
+        let mut cells: Vec<(usize, usize)> = vec![];
+        let mut height = 0;
+        let mut width = 0;
+        for (row, line) in data.lines().enumerate() {
+            for (col, c) in line.chars().enumerate() {
+                if c == '#' {
+                    cells.push((row, col));
+                    width = width.max(col + 1);
+                    height = row + 1;
                 }
             }
-        } else {
-            let mut removals: Vec<(usize, usize)> = vec![];
-            for i in 0..4 {
-                let edges = self.edges.get(i).unwrap();
-                if edges.len() != 1 { continue; }
-                let edge = edges.first().unwrap();
-                let opposing = Dir::opposing_ordinal(&edge.from.to_ordinal());
-                for j in (0..self.edges.get(opposing).unwrap().len()).rev() {
-                    let oppo = self.edges.get(opposing).unwrap().get(j).unwrap();
-                    if oppo.from.is_flipped() != edge.from.is_flipped() {
-                        removals.push((opposing, j));
-                    }
-                }
-                let ortho_twist = edge.is_ortho_flipping();
-                for j in 0..4 {
-                    if j == i || j == opposing { continue; }
-                    for k in (0..self.edges.get(j).unwrap().len()).rev() {
-                        let ortho= self.edges.get(j).unwrap().get(k).unwrap();
-                        if (ortho.from.is_flipped() == edge.from.is_flipped()) != ortho_twist {
-                            removals.push((j, k));
-                        }
-                    }
-                }
-                for (i, j) in removals {
-                    self.edges.get_mut(i).unwrap().remove(j);
-                }
-                break;
-            }
-
         }
-
+        Pattern{cells, height, width}
     }
 
-    fn resolved_connections(&self) -> Vec<Edge> {
-        let mut ret: Vec<Edge> = vec![];
-        for edges in self.edges.iter() {
-            if edges.len() != 1 { continue; }
-            for e in edges {
-                ret.push(e.clone());
-            }
-        }
-        ret
+    fn matches_at(&self, image: &[Vec<char>], row: usize, col: usize) -> bool {
+        self.cells.iter().all(|(r, c)| image[row + r][col + c] == '#')
     }
+}
 
-    fn out_edge(&self, in_edge: &Edge) -> Option<Edge> {
-        let out = Dir::opposing_ordinal(&in_edge.to.to_ordinal());
-        if self.edges[out].len() == 1 {
-            return Some(self.edges[out].first().unwrap().clone());
+#[derive(Debug)]
+struct Search {
+    orientation: Orientation,
+    image: Vec<String>,
+    matches: usize,
+    roughness: usize,
+}
+
+impl Search {
+    fn print(&self) {
+        for line in self.image.iter() {
+            println!("{}", line);
         }
-        None
+        println!("Found {} matches in {:?}, roughness {}", self.matches, self.orientation, self.roughness);
     }
+}
 
-    fn rotate_dir_left(&self, dir: &Dir) -> Option<Edge> {
-        let mut out = dir.to_ordinal();
-        if out == 0 {
-            out = 3;
-        } else {
-            out -= 1;            
+fn search_pattern(image: &[String], pattern: &Pattern, allow_overlap: bool) -> Option<Search> {
+    let mut best: Option<Search> = None;
+    for orientation in Orientation::all() {
+        let variant = orientation.apply_to_grid(image);
+        let mut chars: Vec<Vec<char>> = variant.iter().map(|l| l.chars().collect()).collect();
+        if chars.len() < pattern.height || chars[0].len() < pattern.width { continue; }
+        let mut marked: HashSet<(usize, usize)> = HashSet::new();
+        let mut matches = 0;
+        for row in 0..=chars.len() - pattern.height {
+            for col in 0..=chars[0].len() - pattern.width {
+                if !pattern.matches_at(&chars, row, col) { continue; }
+                let cells: Vec<(usize, usize)> = pattern.cells.iter().map(|(r, c)| (row + r, col + c)).collect();
+                if !allow_overlap && cells.iter().any(|cell| marked.contains(cell)) { continue; }
+                marked.extend(cells);
+                matches += 1;
+            }
         }
-        if self.edges[out].len() == 1 {
-            return Some(self.edges[out].first().unwrap().clone());
+        if matches == 0 || best.as_ref().is_some_and(|b| b.matches >= matches) { continue; }
+        for (row, col) in marked.iter() {
+            chars[*row][*col] = 'O';
         }
-        None
+        let roughness = chars.iter().flatten().filter(|c| **c == '#').count();
+        best = Some(Search{
+            orientation,
+            image: chars.iter().map(|l| l.iter().collect()).collect(),
+            matches,
+            roughness,
+        });
+    }
+    best
+}
+
+fn cell_color(c: char) -> [u8; 3] {
+    match c {
+        '#' => [20, 40, 120],
+        'O' => [40, 200, 60],
+        _ => [150, 200, 240],
     }
+}
 
-    fn rotate_dir_right(&self, dir: &Dir) -> Option<Edge> {
-        let mut out = dir.to_ordinal();
-        out += 1;
-        if out == 4 {
-            out = 0;
-        }
-        if self.edges[out].len() == 1 {
-            return Some(self.edges[out].first().unwrap().clone());
+fn rasterize(image: &[String], scale: usize) -> (usize, usize, Vec<u8>) {
+    let width = image[0].len() * scale;
+    let height = image.len() * scale;
+    let mut pixels: Vec<u8> = Vec::with_capacity(width * height * 3);
+    for line in image {
+        let row: Vec<u8> = line
+            .chars()
+            .flat_map(|c| std::iter::repeat_n(cell_color(c), scale))
+            .flatten()
+            .collect();
+        for _ in 0..scale {
+            pixels.extend(row.iter());
         }
-        None
     }
+    (width, height, pixels)
+}
 
-    fn line(&self, in_edge: &Edge, row: &usize, flipped: &bool) -> (String, usize) {
-        let idx = match flipped { true => self.tile.len() - (row + 1), false => row.clone()};
-        let msg = match in_edge.to {
-            Dir::West | Dir::WestFlipped => self.tile[idx].clone(),
-            Dir::East | Dir::EastFlipped => self.tile[idx].chars().rev().collect(),
-            Dir::South | Dir::SouthFlipped => self.tile
-                .iter()
-                .map(|l| l.chars().enumerate().filter(|(i, _)| *i == idx).map(|(_, c)| c).collect::<String>())
-                .collect(),
-            _ => self.tile
-                .iter()
-                .map(|l| l.chars().enumerate().filter(|(i, _)| *i == idx).map(|(_, c)| c).collect::<String>())
-                .rev()
-                .collect(),
-        };
-        return (msg, idx)
-    }
+fn export_ppm(image: &[String], scale: usize, path: &str) -> std::io::Result<()> {
+    let (width, height, pixels) = rasterize(image, scale);
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    data.extend(pixels);
+    std::fs::write(path, data)
 }
 
-struct Image {
-    tiles: HashMap<usize, Tile>,
+fn export_png(image: &[String], scale: usize, path: &str) -> std::io::Result<()> {
+    let (width, height, pixels) = rasterize(image, scale);
+    let file = std::fs::File::create(path)?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    Ok(())
 }
 
-impl Image {
-    fn print_edgemap(&self, edge: Edge) {
-        let mut tile = self.tiles.get(&edge.target).unwrap();
-        let mut in_edge = tile.out_edge(&edge).unwrap();
-        let mut sec_in_edge = tile.rotate_dir_right(&in_edge.from);
-        loop {
-            print!("{}\t{:?}->{:?}\t", tile.id, in_edge.from, in_edge.to);
-            tile = self.tiles.get(&in_edge.target).unwrap();
-            match tile.out_edge(&in_edge) {
-                Some(e) => {
-                    in_edge = e;
-                }
-                None => {
-                    println!("\t{}",tile.id);
-                    match sec_in_edge {
-                        Some(e2) => {
-                            println!("{:?} v {:?}", e2.from, e2.to);
-                            tile = self.tiles.get(&e2.target).unwrap();
-                            sec_in_edge = tile.out_edge(&e2);
-                            in_edge = tile.rotate_dir_right(&e2.to).unwrap()
-                        },
-                        None => {
-                            break;
-                        } 
-                    }
-                }
-            }
+// Tile positions and orientations are shown in the frame of `view`, e.g. the orientation the pattern was found in
+fn export_svg(layout: &[Vec<Placement>], view: &Orientation, path: &str) -> std::io::Result<()> {
+    let cell = 100;
+    let size = layout.len();
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{0}\" font-family=\"monospace\" text-anchor=\"middle\">\n",
+        size * cell,
+    );
+    for (row, placements) in layout.iter().enumerate() {
+        for (col, placement) in placements.iter().enumerate() {
+            let (row, col) = view.apply_to_position(row, col, size);
+            let orientation = placement.orientation.then(view);
+            let (x, y) = (col * cell, row * cell);
+            svg.push_str(&format!(
+                "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#96c8f0\" stroke=\"#142878\"/>\n",
+                x, y, cell, cell,
+            ));
+            // Mark where the tile's original north edge ended up
+            let (x1, y1, x2, y2) = match orientation.apply_to_edge(0) {
+                0 => (x, y, x + cell, y),
+                1 => (x + cell, y, x + cell, y + cell),
+                2 => (x, y + cell, x + cell, y + cell),
+                _ => (x, y, x, y + cell),
+            };
+            svg.push_str(&format!(
+                "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#c83228\" stroke-width=\"6\"/>\n",
+                x1, y1, x2, y2,
+            ));
+            svg.push_str(&format!(
+                "  <text x=\"{}\" y=\"{}\" font-size=\"20\">{}</text>\n  <text x=\"{}\" y=\"{}\" font-size=\"14\">R{}{}</text>\n",
+                x + cell / 2, y + cell / 2, placement.id,
+                x + cell / 2, y + cell / 2 + 20, orientation.rotation * 90, match orientation.flipped { true => " F", false => "" },
+            ));
         }
     }
+    svg.push_str("</svg>\n");
+    std::fs::write(path, svg)
+}
 
-    fn print_tiled(&self, edge: Edge) {
-        let mut tile = self.tiles.get(&edge.target).unwrap();
-        let mut in_edge = edge;
-        let mut sec_in_edge = tile.rotate_dir_right(&in_edge.from);
-        loop {
-            for idx in 0..tile.tile.len() {
-                let (l, mut row) = tile.line(&in_edge, &idx, &false);
-                print!("{} ", l);
-                match tile.out_edge(&in_edge) {
-                    Some(mut out_edge) => {
-                        let mut flipping = out_edge.to.is_flipped();
-                        loop {
-                            let next = self.tiles.get(&out_edge.target).unwrap();
-                            let (line, n_row) = next.line(&out_edge, &row, &flipping);
-                            print!("{} ", line);
-                            match next.out_edge(&out_edge) {
-                                Some(e) => {
-                                    flipping = out_edge.to.is_flipped() == out_edge.to.is_flipped();
-                                    out_edge = e;
-                                    row = n_row;
-                                },
-                                None => break,
-                            }
-                        }
-                    },
-                    None => {},
-                }
-                println!();
-            }
-            match sec_in_edge {
-                Some(e) => {
-                    //println!("{} -> {} {:?}", tile.id, e.target, e);
-                    tile = self.tiles.get(&e.target).unwrap();
-                    sec_in_edge = tile.out_edge(&e);
-                    println!();
-                },
-                None => break,
-            }
+// Pattern rows from `--pattern PATH`, or `--pattern-rows` with rows separated by '|'
+fn load_pattern(args: &[String]) -> Result<String, String> {
+    let mut rest = args.iter();
+    let mut pattern = load_sea_monster();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--pattern" => {
+                let path = rest.next().ok_or("Missing pattern path")?;
+                pattern = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+            },
+            "--pattern-rows" => pattern = rest.next().ok_or("Missing pattern rows")?.replace('|', "\n"),
+            _ => return Err(format!("Unknown argument {}", arg)),
         }
     }
+    Ok(pattern)
 }
 
 fn main() {
+    // Pattern to search for: [--pattern PATH | --pattern-rows ROW|ROW...], the sea monster by default
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let pattern = match load_pattern(&args).map(|p| Pattern::from_string(&p)) {
+        Ok(pattern) if !pattern.cells.is_empty() => pattern,
+        Ok(_) => {
+            println!("Pattern has no '#' cells");
+            return;
+        },
+        Err(e) => {
+            println!("{}", e);
+            return;
+        },
+    };
     let is_demo = true;
+    let allow_overlap = true;
+    let export = false;
     let data = match is_demo {true => load_demo(), false => load_data()};
     let lines = data.lines();
-    let mut tiles = Tile::from_lines(lines);
-    for i in 0..tiles.len() {
-        for j in i+1..tiles.len() {
-            let i_id = tiles[i].id.clone();
-            let j_id = tiles[j].id.clone();
-            let connections = tiles[i].possible_connections(&tiles[j]);
-            tiles.get_mut(i).unwrap().register_connections(j_id, &connections);
-            tiles.get_mut(j).unwrap().register_connections_rev(i_id, &connections);
+    let tiles = Tile::from_lines(lines);
+    let mut tile_map: HashMap<usize, Tile> = HashMap::new();
+    for tile in tiles {
+        tile_map.insert(tile.id, tile);
+    }
+    let img = Image{tiles: tile_map};
+    let layout = match img.assemble() {
+        Some(layout) => layout,
+        None => {
+            println!("Could not assemble image");
+            return;
+        },
+    };
+    let last = layout.len() - 1;
+    let corners: Vec<usize> = [&layout[0][0], &layout[0][last], &layout[last][0], &layout[last][last]]
+        .iter()
+        .map(|p| p.id)
+        .collect();
+    println!("Part 1: {} / {:?}", corners.iter().product::<usize>(), corners);
+    for row in layout.iter() {
+        println!("{}", row.iter().map(|p| format!("{}{:?}", p.id, (p.orientation.rotation, p.orientation.flipped))).collect::<Vec<String>>().join(" "));
+    }
+    match search_pattern(&stitch(&layout), &pattern, allow_overlap) {
+        Some(search) => {
+            search.print();
+            println!("Part 2: {}", search.roughness);
+            if export {
+                export_ppm(&search.image, 4, "./image.ppm").expect("Could not write image.ppm");
+                export_png(&search.image, 4, "./image.png").expect("Could not write image.png");
+                export_svg(&layout, &search.orientation, "./layout.svg").expect("Could not write layout.svg");
+            }
+        },
+        None => println!("Pattern not found in any orientation"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn demo_tile() -> Vec<String> {
+        Tile::from_lines(load_demo().lines()).remove(0).tile
+    }
+
+    #[test]
+    fn inverse_undoes_orientation() {
+        for a in Orientation::all() {
+            assert_eq!(a.then(&a.inverse()), Orientation::IDENTITY, "{:?} has no inverse", a);
+            assert_eq!(a.inverse().then(&a), Orientation::IDENTITY, "{:?} has no inverse", a);
         }
     }
 
-    let mut corners: Vec<usize> = vec![];
-    for tile in tiles.iter() {
-        println!("Tile {} has {} possible connections, {} amibigous", tile.id, tile.connections(), tile.unresolved_connections());
-        if tile.connections() == 2 {
-            corners.push(tile.id.clone());
+    #[test]
+    fn composition_matches_applying_in_turn() {
+        let grid = demo_tile();
+        for a in Orientation::all() {
+            for b in Orientation::all() {
+                let composed = a.then(&b);
+                assert_eq!(composed.apply_to_grid(&grid), b.apply_to_grid(&a.apply_to_grid(&grid)), "{:?} then {:?}", a, b);
+                for side in 0..4 {
+                    assert_eq!(composed.apply_to_edge(side), b.apply_to_edge(a.apply_to_edge(side)), "{:?} then {:?}", a, b);
+                }
+            }
         }
     }
-    println!("Part 1: {} / {:?}", corners.iter().fold(1, |a, b| a * b), corners);
-    let seed_id: usize = tiles.iter().filter(|t| t.north_west_corner()).next().unwrap().id.clone();
-    let mut tile_map: HashMap<usize, Tile> = HashMap::new();
-    for tile in tiles {
-        tile_map.insert(tile.id.clone(), tile);
+
+    // Sides from North round to West, each read clockwise
+    fn clockwise_sides(grid: &[String]) -> [String; 4] {
+        let south: String = grid.last().unwrap().chars().rev().collect();
+        let west: String = west_side(grid).chars().rev().collect();
+        [grid[0].clone(), east_side(grid), south, west]
     }
-    let mut seeds: Vec<usize> = vec![seed_id];
-    let mut first: bool = true;
-    println!("Using {} as Upper Left", seed_id);
-    loop {
-        match seeds.pop() {
-            Some(seed_id) => {
-                tile_map.get_mut(&seed_id).unwrap().prune_edges(&first);
-                first = false;
-                for e in tile_map.get(&seed_id).unwrap().resolved_connections() {
-                    if e.target == 1427 { println!("{}: {:?} / {:?}", seed_id, e, tile_map.get(&seed_id).unwrap().edges);}
-                    tile_map.get_mut(&e.target).unwrap().prune_other_edges(e.to, seed_id.clone());
-                    if tile_map.get(&e.target).unwrap().unresolved_connections() > 0 {
-                        seeds.push(e.target);
-                    }
-                }
-                // println!("{:?}", seeds);
-                // println!("{}: {:?}", seed_id, tileMap.get(&seed_id).unwrap().edges);
-            },
-            None => {
-                break;
+
+    #[test]
+    fn grid_agrees_with_edges() {
+        let grid = demo_tile();
+        let sides = clockwise_sides(&grid);
+        for a in Orientation::all() {
+            let rotated_sides = clockwise_sides(&a.apply_to_grid(&grid));
+            for (side, text) in sides.iter().enumerate() {
+                let moved = &rotated_sides[a.apply_to_edge(side)];
+                let expected: String = match a.flipped { true => text.chars().rev().collect(), false => text.clone() };
+                assert_eq!(*moved, expected, "{:?} moves side {}", a, side);
             }
         }
     }
-    for (k, v) in tile_map.iter() {
-        println!("{}: {} / {}", k, v.resolved_connections().len(), v.unresolved_connections())
+
+    #[test]
+    fn grid_agrees_with_positions() {
+        let grid = demo_tile();
+        for a in Orientation::all() {
+            let rotated = a.apply_to_grid(&grid);
+            for (row, line) in grid.iter().enumerate() {
+                for (col, c) in line.chars().enumerate() {
+                    let (r, c2) = a.apply_to_position(row, col, grid.len());
+                    assert_eq!(rotated[r].chars().nth(c2), Some(c), "{:?} moves ({}, {})", a, row, col);
+                }
+            }
+        }
     }
-    let img = Image{tiles: tile_map};
-    img.print_tiled(Edge{target: seed_id, from: Dir::East, to: Dir::West});
-    img.print_edgemap(Edge{target: seed_id, from: Dir::East, to: Dir::West });
 }
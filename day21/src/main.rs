@@ -1,4 +1,6 @@
 use std::collections::{HashMap, HashSet};
+use constraints::{Problem, Solution};
+use serde_json::json;
 
 fn load_demo() -> String {
     r#"mxmxvkd kfcds sqjhc nhms (contains dairy, fish)
@@ -49,6 +51,7 @@ struct Allergen {
     name: String,
     foods: Vec<usize>,
     candidates: Vec<String>,
+    ingredient: Option<String>,
 }
 
 impl Allergen {
@@ -59,111 +62,248 @@ impl Allergen {
                 name: a.clone(),
                 foods: vec![id_food.clone()],
                 candidates: food.ingredients.clone(),
+                ingredient: None,
             });
         }
         all
     }
 
-    fn trim_candidates(&mut self, food: &Food, id_food: &usize) {
+    fn trim_candidates(&mut self, food: &Food, id_food: &usize) -> Vec<String> {
         self.foods.push(id_food.clone());
+        let mut removed: Vec<String> = vec![];
         for idx in (0..self.candidates.len()).rev() {
             if !food.ingredients.contains(&self.candidates[idx]) {
-                self.candidates.remove(idx);
+                removed.push(self.candidates.remove(idx));
             }
         }
+        removed.reverse();
+        removed
     }
 
-    fn purge(&mut self, word: &String) {
-        for idx in (0..self.candidates.len()).rev() {
-            if self.candidates[idx] == *word {
-                self.candidates.remove(idx);
-            }
-        }
+    fn all_word(&self) -> String {
+        self.ingredient.clone().expect("Allergen not resolved")
     }
+}
 
-    fn determined(&self) -> bool {
-        self.candidates.len() == 1
+#[derive(Debug)]
+enum Step {
+    Seen{allergen: String, food: usize, candidates: usize},
+    Intersect{allergen: String, food: usize, removed: Vec<String>},
+    Purge{allergen: String, ingredient: String, removed_from: Vec<String>},
+    Search{allergen: String, ingredient: String},
+    Retract{food: usize, recomputed: Vec<String>},
+    Contradiction{allergen: String, food: usize},
+}
+
+impl Step {
+    fn describe(&self) -> String {
+        match self {
+            Step::Seen{allergen, food, candidates} => format!(
+                "{} first listed in food {}, {} candidate ingredients", allergen, food, candidates,
+            ),
+            Step::Intersect{allergen, food, removed} => format!(
+                "{} also in food {}, which lacks {}", allergen, food, removed.join(", "),
+            ),
+            Step::Purge{allergen, ingredient, removed_from} if removed_from.is_empty() => format!(
+                "{} is the only candidate left for {}", ingredient, allergen,
+            ),
+            Step::Purge{allergen, ingredient, removed_from} => format!(
+                "{} is the only candidate left for {}, removed from {}", ingredient, allergen, removed_from.join(", "),
+            ),
+            Step::Search{allergen, ingredient} => format!(
+                "{} assigned to {} as the only consistent choice", ingredient, allergen,
+            ),
+            Step::Retract{food, recomputed} => format!(
+                "food {} retracted, candidates recomputed for {}", food, recomputed.join(", "),
+            ),
+            Step::Contradiction{allergen, food} => format!(
+                "food {} leaves no candidate for {}, rejected", food, allergen,
+            ),
+        }
     }
 
-    fn all_word(&self) -> String {
-        self.candidates[0].clone()
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Step::Seen{allergen, food, candidates} => json!({
+                "step": "seen", "allergen": allergen, "food": food, "candidates": candidates,
+            }),
+            Step::Intersect{allergen, food, removed} => json!({
+                "step": "intersect", "allergen": allergen, "food": food, "removed": removed,
+            }),
+            Step::Purge{allergen, ingredient, removed_from} => json!({
+                "step": "purge", "allergen": allergen, "ingredient": ingredient, "removed_from": removed_from,
+            }),
+            Step::Search{allergen, ingredient} => json!({
+                "step": "search", "allergen": allergen, "ingredient": ingredient,
+            }),
+            Step::Retract{food, recomputed} => json!({
+                "step": "retract", "food": food, "recomputed": recomputed,
+            }),
+            Step::Contradiction{allergen, food} => json!({
+                "step": "contradiction", "allergen": allergen, "food": food,
+            }),
+        }
     }
 }
 
 struct Translation {
-    foods: Vec<Food>,
+    foods: Vec<Option<Food>>,
     words: HashMap<String, Vec<usize>>,
     allergens: HashMap<String, Allergen>,    
+    log: Vec<Step>,
 }
 
 impl Translation {
-    fn from_data(data: String) -> Translation {
-        let mut foods: Vec<Food> = vec![];
-        let mut allergens: HashMap<String, Allergen> = HashMap::new();
-        let mut words: HashMap<String, Vec<usize>> = HashMap::new();
-        for (id_food, line) in data.lines().enumerate() {
-            let f = Food::from_line(line);
-            for word in f.ingredients.iter() {
-                if words.contains_key(word) {
-                    words.get_mut(word).unwrap().push(id_food.clone());
-                } else {
-                    words.insert(word.clone(), vec![id_food.clone()]);
-                }
-            }
-            for all in Allergen::list_from_food(&f, &id_food) {
-                if allergens.contains_key(&all.name) {
-                    allergens.get_mut(&all.name).unwrap().trim_candidates(&f, &id_food);
-                } else {
-                    allergens.insert(all.name.clone(), all);
-                }
-            }
+    fn new() -> Translation {
+        Translation{foods: vec![], allergens: HashMap::new(), words: HashMap::new(), log: vec![]}
+    }
+
+    fn from_data(data: String) -> Result<Translation, String> {
+        let mut trans = Translation::new();
+        for line in data.lines() {
+            trans.ingest(line)?;
+        }
+        Ok(trans)
+    }
 
-            foods.push(f);
-        }
-        Translation{foods, allergens, words}
-    }
-
-    fn resolve_allergens(&mut self) {
-        let mut determined: HashSet<String> = HashSet::new();
-        let keys: Vec<String> = self.allergens.keys().map(|k| k.clone()).collect();
-        let mut trim_cycle = 0;
-        loop {
-            for all_name in keys.iter() {
-                if determined.contains(all_name) { continue; }
-                if self.allergens[all_name].determined() {
-                    let all_word = self.allergens[all_name].all_word();
-                    for food in self.words[&all_word].iter() {
-                        for other_all_name in self.foods[*food].allergens.iter() {
-                            if other_all_name == all_name || self.allergens[other_all_name].determined() { continue; }
-                            self.allergens.get_mut(other_all_name).unwrap().purge(&all_word);
-                        }
+    fn ingest(&mut self, line: &str) -> Result<usize, String> {
+        let id_food = self.foods.len();
+        let f = Food::from_line(line);
+        for word in f.ingredients.iter() {
+            self.words.entry(word.clone()).or_default().push(id_food);
+        }
+        let mut contradiction: Option<String> = None;
+        for all in Allergen::list_from_food(&f, &id_food) {
+            match self.allergens.get_mut(&all.name) {
+                Some(known) => {
+                    let removed = known.trim_candidates(&f, &id_food);
+                    if !removed.is_empty() {
+                        self.log.push(Step::Intersect{allergen: all.name.clone(), food: id_food, removed});
                     }
-                    determined.insert(all_name.clone());
-                }
-            }
-            trim_cycle += 1;
-            println!("{}: {} determined of {}", trim_cycle, determined.len(), keys.len());
-            if determined.len() == keys.len() {
-                break;
+                    if known.candidates.is_empty() && contradiction.is_none() {
+                        contradiction = Some(all.name.clone());
+                    }
+                },
+                None => {
+                    self.log.push(Step::Seen{allergen: all.name.clone(), food: id_food, candidates: all.candidates.len()});
+                    self.allergens.insert(all.name.clone(), all);
+                },
             }
         }
+        self.foods.push(Some(f));
+        match contradiction {
+            Some(allergen) => Err(self.reject(id_food, line, allergen)),
+            None => Ok(id_food),
+        }
     }
 
-    fn non_allergenics(&self) -> HashSet<String> {
-        let mut nons = HashSet::new();
-        for word in self.words.keys() {
-            let mut found = false;
-            for (_, all) in self.allergens.iter() {
-                if *word == all.all_word() {
-                    found = true;
-                    break;
-                }
+    // Takes back the food just ingested, which left an allergen without candidates
+    fn reject(&mut self, id_food: usize, line: &str, allergen: String) -> String {
+        self.log.push(Step::Contradiction{allergen: allergen.clone(), food: id_food});
+        self.retract(id_food);
+        self.foods.pop();
+        format!("Food {} ({}) leaves no candidate ingredient for {}", id_food, line, allergen)
+    }
+
+    fn retract(&mut self, id_food: usize) -> Option<Food> {
+        let f = self.foods.get_mut(id_food)?.take()?;
+        // A food listing a word twice finds it already gone the second time
+        for word in f.ingredients.iter() {
+            let foods = match self.words.get_mut(word) {
+                Some(foods) => foods,
+                None => continue,
+            };
+            foods.retain(|id| *id != id_food);
+            if foods.is_empty() {
+                self.words.remove(word);
+            }
+        }
+        let mut recomputed: Vec<String> = vec![];
+        for name in f.allergens.iter() {
+            let all = match self.allergens.get_mut(name) {
+                Some(all) if all.foods.contains(&id_food) => all,
+                _ => continue,
+            };
+            all.foods.retain(|id| *id != id_food);
+            if all.foods.is_empty() {
+                self.allergens.remove(name);
+                continue;
             }
-            if !found {
-                nons.insert(word.clone());
+            let foods = &self.foods;
+            let remaining: Vec<&Food> = all.foods.iter().filter_map(|id| foods[*id].as_ref()).collect();
+            all.candidates = remaining[0].ingredients
+                .iter()
+                .filter(|w| remaining.iter().all(|other| other.ingredients.contains(w)))
+                .cloned()
+                .collect();
+            recomputed.push(name.clone());
+        }
+        self.log.push(Step::Retract{food: id_food, recomputed});
+        Some(f)
+    }
+
+    // Also rejects a food that only empties an allergen once known ingredients are purged
+    fn add_food(&mut self, line: &str) -> Result<usize, String> {
+        let id_food = self.ingest(line)?;
+        if let Err(allergen) = self.problem().propagate() {
+            return Err(self.reject(id_food, line, allergen));
+        }
+        let _ = self.resolve_allergens();
+        Ok(id_food)
+    }
+
+    fn remove_food(&mut self, id_food: usize) -> Result<Food, String> {
+        let f = self.retract(id_food).ok_or(format!("No food {} to remove", id_food))?;
+        let _ = self.resolve_allergens();
+        Ok(f)
+    }
+
+    fn is_resolved(&self) -> bool {
+        self.allergens.values().all(|all| all.ingredient.is_some())
+    }
+
+    fn problem(&self) -> Problem<String, String> {
+        let mut problem: Problem<String, String> = Problem::new();
+        for (name, all) in self.allergens.iter() {
+            problem.restrict(name.clone(), all.candidates.iter().cloned());
+        }
+        problem
+    }
+
+    fn resolve_allergens(&mut self) -> Result<(), String> {
+        let mut problem = self.problem();
+        for all in self.allergens.values_mut() {
+            all.ingredient = None;
+        }
+        if let Ok(deductions) = problem.propagate() {
+            for d in deductions {
+                self.log.push(Step::Purge{allergen: d.key, ingredient: d.value, removed_from: d.eliminated_from});
             }
         }
-        nons
+        match problem.solve(2) {
+            Solution::Unique(assignment) => {
+                for (name, word) in assignment {
+                    if problem.determined(&name).is_none() {
+                        self.log.push(Step::Search{allergen: name.clone(), ingredient: word.clone()});
+                    }
+                    self.allergens.get_mut(&name).unwrap().ingredient = Some(word);
+                }
+                Ok(())
+            },
+            Solution::Ambiguous(assignments) => Err(format!(
+                "Ambiguous allergens, e.g. {:?} or {:?}", assignments[0], assignments[1],
+            )),
+            Solution::Impossible(Some(name)) => Err(format!("No ingredient left for {}", name)),
+            Solution::Impossible(None) => Err("Allergens can't all have distinct ingredients".to_string()),
+        }
+    }
+
+    fn non_allergenics(&self) -> HashSet<String> {
+        self.words
+            .keys()
+            .filter(|word| self.allergens.values().all(|all| !all.candidates.contains(word)))
+            .cloned()
+            .collect()
     }
 
     fn count_non_allergenics(&self) -> usize {
@@ -172,6 +312,7 @@ impl Translation {
         self
             .foods
             .iter()
+            .flatten()
             .fold(
             0,
             |acc, f| acc + f
@@ -182,6 +323,29 @@ impl Translation {
             )
     }
 
+    fn print_log(&self) {
+        for (idx, step) in self.log.iter().enumerate() {
+            println!("{}: {}", idx + 1, step.describe());
+        }
+    }
+
+    fn report(&self) -> serde_json::Value {
+        let mapping: serde_json::Map<String, serde_json::Value> = self.allergens
+            .iter()
+            .map(|(name, all)| (name.clone(), json!(all.all_word())))
+            .collect();
+        let safe: serde_json::Map<String, serde_json::Value> = self.non_allergenics()
+            .iter()
+            .map(|word| (word.clone(), json!(self.words[word].len())))
+            .collect();
+        json!({
+            "allergens": mapping,
+            "safe_ingredients": safe,
+            "dangerous_ingredients": self.alphabetical_alergens().join(","),
+            "deductions": self.log.iter().map(|step| step.to_json()).collect::<Vec<serde_json::Value>>(),
+        })
+    }
+
     fn alphabetical_alergens(&self) -> Vec<String> {
         let mut keys: Vec<String> = self.allergens.keys().map(|k| k.clone()).collect();
         keys.sort();
@@ -194,11 +358,77 @@ impl Translation {
 
 fn main() {
     let is_demo = false;
+    let export = false;
+    let incremental = false;
+    let retractions: Vec<usize> = vec![];
     let data = match is_demo { true => load_demo(), false => load_data()};
-    let mut trans = Translation::from_data(data);
-    trans.resolve_allergens();
+    let mut trans = match incremental {
+        true => {
+            let mut trans = Translation::new();
+            for line in data.lines() {
+                match trans.add_food(line) {
+                    Ok(id_food) => println!("Added food {}, resolved: {}", id_food, trans.is_resolved()),
+                    Err(msg) => println!("{}", msg),
+                }
+            }
+            for id_food in retractions {
+                match trans.remove_food(id_food) {
+                    Ok(_) => println!("Removed food {}, resolved: {}", id_food, trans.is_resolved()),
+                    Err(msg) => println!("{}", msg),
+                }
+            }
+            trans
+        },
+        false => match Translation::from_data(data) {
+            Ok(trans) => trans,
+            Err(msg) => {
+                println!("{}", msg);
+                return;
+            },
+        },
+    };
+    if let Err(msg) = trans.resolve_allergens() {
+        println!("{}", msg);
+        return;
+    }
     let count = trans.count_non_allergenics();
     println!("Part 1: {}", count);
     let words = trans.alphabetical_alergens();
     println!("Part 2: {}", words.join(","));
+    trans.print_log();
+    if export {
+        let report = serde_json::to_string_pretty(&trans.report()).unwrap();
+        std::fs::write("./report.json", report).expect("Could not write report.json");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_food_rejects_contradiction_after_propagation() {
+        let mut trans = Translation::new();
+        assert_eq!(trans.add_food("a b (contains x)"), Ok(0));
+        assert_eq!(trans.add_food("a (contains y)"), Ok(1));
+        let err = trans.add_food("a c (contains x)").unwrap_err();
+        assert!(err.starts_with("Food 2 "), "{}", err);
+        assert_eq!(trans.foods.len(), 2);
+        assert_eq!(trans.allergens["x"].candidates, vec!["a", "b"]);
+        assert!(trans.resolve_allergens().is_ok());
+        assert_eq!(trans.alphabetical_alergens(), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn remove_food_with_repeated_entries() {
+        let mut trans = Translation::new();
+        assert_eq!(trans.add_food("a a b (contains x, x)"), Ok(0));
+        assert_eq!(trans.add_food("b c (contains x)"), Ok(1));
+        assert!(trans.remove_food(0).is_ok());
+        assert!(!trans.words.contains_key("a"));
+        assert_eq!(trans.allergens["x"].foods, vec![1]);
+        assert_eq!(trans.allergens["x"].candidates, vec!["b", "c"]);
+        assert!(trans.remove_food(1).is_ok());
+        assert!(trans.words.is_empty() && trans.allergens.is_empty());
+    }
 }
@@ -1,5 +1,6 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
+use serde_json::json;
 
 fn load_demo() -> String {
     r#"Player 1:
@@ -28,17 +29,71 @@ Player 2:
 14"#.to_string()
 }
 
+fn load_demo3() -> String {
+    r#"Player 1:
+9
+2
+6
+
+Player 2:
+5
+8
+4
+
+Player 3:
+7
+3
+1"#.to_string()
+}
+
 fn load_data() -> String {
     fs::read_to_string("./input.txt").unwrap()
 }
 
+// Decks keep a polynomial hash of their cards, updated as cards leave the top and join the bottom
+const HASH_MOD: u64 = (1 << 61) - 1;
+const HASH_BASE: u64 = 1_000_003;
+const HASH_BASE_INV: u64 = pow_mod(HASH_BASE, HASH_MOD - 2);
+
+const fn mul_mod(a: u64, b: u64) -> u64 {
+    ((a as u128 * b as u128) % HASH_MOD as u128) as u64
+}
+
+const fn pow_mod(mut base: u64, mut exp: u64) -> u64 {
+    let mut acc = 1;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            acc = mul_mod(acc, base);
+        }
+        base = mul_mod(base, base);
+        exp >>= 1;
+    }
+    acc
+}
+
 #[derive(Debug, Clone)]
 struct Deck {
     player: String,
     cards: VecDeque<usize>,
+    hash: u64,
+    pow: u64,
 }
 
 impl Deck {
+    fn new(player: String, cards: VecDeque<usize>) -> Deck {
+        let mut deck = Deck{player, cards: VecDeque::new(), hash: 0, pow: 1};
+        for card in cards {
+            deck.push_card(card);
+        }
+        deck
+    }
+
+    fn push_card(&mut self, card: usize) {
+        self.cards.push_back(card);
+        self.hash = (mul_mod(self.hash, HASH_BASE) + card as u64) % HASH_MOD;
+        self.pow = mul_mod(self.pow, HASH_BASE);
+    }
+
     fn from_string(data: String) -> Vec<Deck> {
         let mut decks = vec![];
         let mut player: String = "".to_string();
@@ -47,7 +102,7 @@ impl Deck {
             if line.starts_with("Player") {
                 player = line.trim().replace(":", "");                
             } else if line.is_empty() {
-                decks.push(Deck{player, cards});
+                decks.push(Deck::new(player, cards));
                 player = "".to_string();
                 cards = VecDeque::new();
             } else {
@@ -56,22 +111,22 @@ impl Deck {
             }
         }
         if !player.is_empty() {
-            decks.push(Deck{player, cards});
+            decks.push(Deck::new(player, cards));
         }
         decks
     }
 
     fn get_card(&mut self) -> Option<usize> {
-        self.cards.pop_front()
-    }
-
-    fn gain_cards(&mut self, own_card: usize, opponent_card: usize) {
-        self.cards.push_back(own_card);
-        self.cards.push_back(opponent_card);
+        let card = self.cards.pop_front()?;
+        self.pow = mul_mod(self.pow, HASH_BASE_INV);
+        self.hash = (self.hash + HASH_MOD - mul_mod(card as u64, self.pow)) % HASH_MOD;
+        Some(card)
     }
 
-    fn return_card(&mut self, card: usize) {
-        self.cards.push_front(card);        
+    fn gain_cards(&mut self, cards: &[usize]) {
+        for card in cards {
+            self.push_card(*card);
+        }
     }
 
     fn score(&self) -> usize {
@@ -103,155 +158,368 @@ impl Deck {
     }
 
     fn trim_deck_clone(&self, size: &usize) -> Deck {
-        let mut cards = self.cards.clone();
-        while cards.len() > *size {
-            cards.pop_back();
-        }
-        Deck{
-            player: self.player.clone(),
-            cards 
+        Deck::new(self.player.clone(), self.cards.iter().take(*size).cloned().collect())
+    }
+}
+
+trait Rules {
+    fn name(&self) -> &str;
+
+    // If a repeated configuration within a game is won by the first player still holding cards
+    fn repeat_guard(&self) -> bool {
+        false
+    }
+
+    // If the round is settled by a sub-game with decks trimmed to the played cards,
+    // where `cards[i]` was played from `decks[active[i]]`
+    fn sub_game(&self, _decks: &[Deck], _active: &[usize], _cards: &[usize]) -> bool {
+        false
+    }
+
+    fn round_winner(&self, cards: &[usize]) -> usize {
+        (0..cards.len()).max_by_key(|idx| cards[*idx]).unwrap()
+    }
+
+    // A winner known without playing the game out
+    fn shortcut(&self, _decks: &[Deck]) -> Option<usize> {
+        None
+    }
+}
+
+struct Standard;
+
+impl Rules for Standard {
+    fn name(&self) -> &str {
+        "standard"
+    }
+}
+
+struct Recursive;
+
+impl Rules for Recursive {
+    fn name(&self) -> &str {
+        "recursive"
+    }
+
+    fn repeat_guard(&self) -> bool {
+        true
+    }
+
+    fn sub_game(&self, decks: &[Deck], active: &[usize], cards: &[usize]) -> bool {
+        active.iter().zip(cards).all(|(idx, card)| decks[*idx].cards() >= *card)
+    }
+
+    // If the first player holds a card too high to ever be played into a sub-game, no one can take it
+    // from them, so they either win outright or by the repeat rule. This does not hold for the others.
+    fn shortcut(&self, decks: &[Deck]) -> Option<usize> {
+        let total: usize = decks.iter().map(|d| d.cards()).sum();
+        let highest = decks.iter().flat_map(|d| d.cards.iter()).max()?;
+        match decks[0].cards.contains(highest) && *highest + 2 > total {
+            true => Some(0),
+            false => None,
         }
     }
 }
 
-fn play(mut decks: Vec<Deck>) {
-    loop {
-        match decks[0].get_card() {
-            Some(player_one) => {
-                match decks[1].get_card() {
-                    Some(player_two) => {
-                        if player_one > player_two {
-                            decks[0].gain_cards(player_one, player_two);
-                        } else {
-                            decks[1].gain_cards(player_two, player_one);
-                        }
-                    },
-                    None => {
-                        decks[0].return_card(player_one);
-                        break;
-                    }
-                }
-            },
-            None => {
-                break;
-            }        
-        }        
+struct CustomTrigger {
+    name: String,
+    trigger: fn(&[Deck], &[usize], &[usize]) -> bool,
+}
+
+impl Rules for CustomTrigger {
+    fn name(&self) -> &str {
+        &self.name
     }
 
-    for deck in decks.iter() {
-        println!("{} scores: {}", deck.player, deck.score());
+    fn repeat_guard(&self) -> bool {
+        true
+    }
+
+    fn sub_game(&self, decks: &[Deck], active: &[usize], cards: &[usize]) -> bool {
+        (self.trigger)(decks, active, cards)
     }
 }
 
-fn play_recursive(mut d1: Deck, mut d2: Deck, game: usize, verbose: &bool) -> usize {
-    let mut round = 1;
-    let mut player_history: Vec<HashSet<VecDeque<usize>>> = vec![
-        HashSet::new(),
-        HashSet::new(),
-    ];
-    if *verbose {
-        println!("\n=== Game {} ===", game);
+#[derive(Debug, Clone)]
+struct Round {
+    cards: Vec<usize>,
+    sub_game: Option<GameLog>,
+    winner: usize,
+}
+
+#[derive(Debug, Clone)]
+struct GameLog {
+    game: usize,
+    decks: Vec<Deck>,
+    rounds: Vec<Round>,
+    winner: usize,
+    repeated: bool,
+}
+
+impl GameLog {
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "game": self.game,
+            "decks": self.decks.iter().map(|d| json!({"player": d.player, "cards": d.cards})).collect::<Vec<_>>(),
+            "rounds": self.rounds.iter().map(|r| json!({
+                "cards": r.cards,
+                "sub_game": r.sub_game.as_ref().map(|g| g.to_json()),
+                "winner": r.winner,
+            })).collect::<Vec<_>>(),
+            "winner": self.winner,
+            "repeated": self.repeated,
+        })
     }
-    loop {
-        if player_history[0].contains(&d1.cards) {
+
+    // Plays the recorded rounds back from the starting decks, checking they reach the recorded end
+    fn replay(&self, verbose: &bool) -> Result<Vec<Deck>, String> {
+        let mut decks = self.decks.clone();
+        if *verbose {
+            println!("\n=== Replay game {} ===", self.game);
+        }
+        for (idx, round) in self.rounds.iter().enumerate() {
+            let active: Vec<usize> = (0..decks.len()).filter(|p| decks[*p].cards() > 0).collect();
+            for (player, card) in active.iter().zip(round.cards.iter()) {
+                let deck = &mut decks[*player];
+                if deck.get_card() != Some(*card) {
+                    return Err(format!("{} can't play {} in round {} of game {}", deck.player, card, idx + 1, self.game));
+                }
+            }
+            let position = active
+                .iter()
+                .position(|p| *p == round.winner)
+                .ok_or(format!("Round {} of game {} won by a player out of cards", idx + 1, self.game))?;
+            if let Some(sub_game) = round.sub_game.as_ref() {
+                sub_game.replay(verbose)?;
+                if sub_game.winner != position {
+                    return Err(format!("Sub-game of round {} of game {} has another winner", idx + 1, self.game));
+                }
+            }
             if *verbose {
-                println!("Player 1 wins round {} in game {} on infinite recursion", round, game);
-                println!("{}'s deck {}", d1.player, d1.cards_to_string(&0));
+                println!("{} wins round {} of game {} with {:?}", decks[round.winner].player, idx + 1, self.game, round.cards);
             }
-            return 0;
-        } else {
-            player_history.get_mut(0).unwrap().insert(d1.cards.clone());
+            decks[round.winner].gain_cards(&collected(&round.cards, position));
+        }
+        Ok(decks)
+    }
+}
+
+// Winner's card first, then the others from highest to lowest
+fn collected(cards: &[usize], winner: usize) -> Vec<usize> {
+    let mut others: Vec<usize> = cards.iter().enumerate().filter(|(idx, _)| *idx != winner).map(|(_, c)| *c).collect();
+    others.sort_by(|a, b| b.cmp(a));
+    let mut out = vec![cards[winner]];
+    out.extend(others);
+    out
+}
+
+struct Engine<'a> {
+    rules: &'a dyn Rules,
+    verbose: bool,
+    record: bool,
+    fast: bool,
+    games: usize,
+    cache: HashMap<Vec<VecDeque<usize>>, usize>,
+    cache_hits: usize,
+    shortcuts: usize,
+}
+
+impl<'a> Engine<'a> {
+    fn new(rules: &'a dyn Rules, verbose: bool, record: bool) -> Engine<'a> {
+        Engine{
+            rules,
+            verbose,
+            record,
+            fast: !record,
+            games: 0,
+            cache: HashMap::new(),
+            cache_hits: 0,
+            shortcuts: 0,
         }
-        if player_history[1].contains(&d1.cards) {
+    }
 
-            println!("Player 1 wins round {} in game {} on infinite recursion", round, game);
-            if *verbose {
-                println!("{}'s deck {}", d2.player, d2.cards_to_string(&0));
-            }
-            return 0;
-        } else {
-            player_history.get_mut(0).unwrap().insert(d2.cards.clone());
+    // Sub-games only decide a winner, so they can be looked up or shortcut unless the full tree is recorded
+    fn sub_game(&mut self, decks: Vec<Deck>) -> GameLog {
+        if !self.fast {
+            return self.play(decks).1;
         }
-        match d1.get_card() {
-            Some(player_one) => {
-                match d2.get_card() {
-                    Some(player_two) => {
-                        if *verbose {
-                            println!("\n--Round {} (Game {}) --", round, game);
-                            println!("{}'s deck {}", d1.player, d1.cards_to_string(&player_one));
-                            println!("{}'s deck {}", d2.player, d2.cards_to_string(&player_two));
-                            println!("{} plays: {}", d1.player, player_one);
-                            println!("{} plays: {}", d2.player, player_two);
-                        }
-                        if d1.cards() + 1 > player_one && d2.cards() + 1 > player_two {
-                            if *verbose {
-                                println!("Playing a sub-game to determine the winner...");
-                            }
-                            match play_recursive(
-                                d1.trim_deck_clone(&player_one),
-                                d2.trim_deck_clone(&player_two),
-                                game + 1,
-                                verbose,
-                            ) {
-                                0 => {
-                                    if *verbose {
-                                        println!("\n...anyway, back to game {}", game);
-                                        println!("{} wins round {} of game {}!", d1.player, round, game);
-                                    }
-                                    d1.gain_cards(player_one, player_two);
-                                }
-                                1 => {
-                                    if *verbose {
-                                        println!("\n...anyway, back to game {}", game);
-                                        println!("{} wins round {} of game {}!", d2.player, round, game);
-                                    }
-                                    d2.gain_cards(player_two, player_one);
-                                }
-                                _ => panic!("Unknown winner of game!")
-                            }
-                        } else if player_one > player_two {
-                            if *verbose {
-                                println!("{} wins round {} of game {}!", d1.player, round, game);
-                            }
-                            d1.gain_cards(player_one, player_two);
-                        } else {
-                            if *verbose {
-                                println!("{} wins round {} of game {}!", d2.player, round, game);
-                            }
-                            d2.gain_cards(player_two, player_one);
-                        }
-                    },
-                    None => {
-                        d1.return_card(player_one);
-                        break;
-                    }
-                }
-            },
-            None => {
+        let empty = |winner| GameLog{game: 0, decks: vec![], rounds: vec![], winner, repeated: false};
+        if let Some(winner) = self.rules.shortcut(&decks) {
+            self.shortcuts += 1;
+            return empty(winner);
+        }
+        let key: Vec<VecDeque<usize>> = decks.iter().map(|d| d.cards.clone()).collect();
+        if let Some(winner) = self.cache.get(&key) {
+            self.cache_hits += 1;
+            return empty(*winner);
+        }
+        let (_, log) = self.play(decks);
+        self.cache.insert(key, log.winner);
+        log
+    }
+
+    fn play(&mut self, mut decks: Vec<Deck>) -> (Vec<Deck>, GameLog) {
+        self.games += 1;
+        let game = self.games;
+        let mut log = GameLog{game, decks: vec![], rounds: vec![], winner: 0, repeated: false};
+        if self.record {
+            log.decks = decks.clone();
+        }
+        let mut history: HashSet<Vec<(usize, u64)>> = HashSet::new();
+        if self.verbose {
+            println!("\n=== Game {} ===", game);
+        }
+        let mut round = 1;
+        loop {
+            let active: Vec<usize> = (0..decks.len()).filter(|idx| decks[*idx].cards() > 0).collect();
+            if active.len() < 2 {
                 break;
-            }        
+            }
+            if self.rules.repeat_guard() && !history.insert(decks.iter().map(|d| (d.cards(), d.hash)).collect()) {
+                if self.verbose {
+                    println!("{} wins round {} in game {} on infinite recursion", decks[active[0]].player, round, game);
+                }
+                log.repeated = true;
+                log.winner = active[0];
+                return (decks, log);
+            }
+            if self.verbose {
+                println!("\n-- Round {} (Game {}) --", round, game);
+                for idx in active.iter() {
+                    println!("{}'s deck {}", decks[*idx].player, decks[*idx].cards_to_string(&0));
+                }
+            }
+            let cards: Vec<usize> = active.iter().map(|idx| decks[*idx].get_card().unwrap()).collect();
+            if self.verbose {
+                for (idx, card) in active.iter().zip(cards.iter()) {
+                    println!("{} plays: {}", decks[*idx].player, card);
+                }
+            }
+            let mut sub_game: Option<GameLog> = None;
+            let position = match self.rules.sub_game(&decks, &active, &cards) {
+                true => {
+                    if self.verbose {
+                        println!("Playing a sub-game to determine the winner...");
+                    }
+                    let sub_decks = active.iter().zip(cards.iter()).map(|(idx, c)| decks[*idx].trim_deck_clone(c)).collect();
+                    let sub_log = self.sub_game(sub_decks);
+                    if self.verbose {
+                        println!("\n...anyway, back to game {}", game);
+                    }
+                    let position = sub_log.winner;
+                    if self.record {
+                        sub_game = Some(sub_log);
+                    }
+                    position
+                },
+                false => self.rules.round_winner(&cards),
+            };
+            let winner = active[position];
+            if self.verbose {
+                println!("{} wins round {} of game {}!", decks[winner].player, round, game);
+            }
+            decks[winner].gain_cards(&collected(&cards, position));
+            if self.record {
+                log.rounds.push(Round{cards, sub_game, winner});
+            }
+            round += 1;
         }
-        round += 1;     
+        log.winner = (0..decks.len()).max_by_key(|idx| decks[*idx].cards()).unwrap();
+        (decks, log)
     }
+}
 
-    if *verbose || game == 1 {
-        println!("{} scores: {}", d1.player, d1.score());
-        println!("{} scores: {}", d2.player, d2.score());
+fn play(rules: &dyn Rules, decks: Vec<Deck>, verbose: bool, record: bool) -> GameLog {
+    let mut engine = Engine::new(rules, verbose, record);
+    let (decks, log) = engine.play(decks);
+    println!(
+        "{} combat over {} games ({} cached, {} shortcut):",
+        rules.name(), engine.games, engine.cache_hits, engine.shortcuts,
+    );
+    for deck in decks.iter() {
+        println!("{} scores: {}", deck.player, deck.score());
+    }
+    println!("{} wins", decks[log.winner].player);
+    log
+}
+
+// Deals cards 1..=cards shuffled by a fixed linear congruential generator
+fn generate_decks(cards: usize, players: usize, seed: u64) -> Vec<Deck> {
+    let mut pile: Vec<usize> = (1..=cards).collect();
+    let mut state = seed;
+    for idx in (1..pile.len()).rev() {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        pile.swap(idx, (state >> 33) as usize % (idx + 1));
+    }
+    (0..players)
+        .map(|p| Deck::new(
+            format!("Player {}", p + 1),
+            pile.iter().skip(p).step_by(players).cloned().collect(),
+        ))
+        .collect()
+}
+
+fn benchmark(sizes: &[usize], seeds: u64) {
+    for size in sizes {
+        for fast in [false, true] {
+            let start = std::time::Instant::now();
+            let mut games = 0;
+            for seed in 0..seeds {
+                let mut engine = Engine::new(&Recursive, false, false);
+                engine.fast = fast;
+                engine.play(generate_decks(*size, 2, seed));
+                games += engine.games;
+            }
+            println!(
+                "{} cards, {} deals, {}: {} games in {:?}",
+                size, seeds, match fast { true => "cached", false => "plain" }, games, start.elapsed(),
+            );
+        }
     }
-    match d1.cards() > 0 { true => 0, false => 1 }
 }
 
 fn main() {
     let verbose = false;
     let is_demo = false;
     let is_demo2 = false;
-    let data = match is_demo {
-        true => match is_demo2 { true => load_demo2(), false => load_demo()},
-        false => load_data(),
+    let is_demo3 = false;
+    let generated: Option<(usize, usize)> = None;
+    let record = false;
+    let variant: Option<&str> = None;
+    let bench = false;
+    if bench {
+        benchmark(&[30, 40, 50, 60], 5);
+        return;
+    }
+    let data = match (is_demo, is_demo2, is_demo3) {
+        (true, true, _) => load_demo2(),
+        (true, _, true) => load_demo3(),
+        (true, _, _) => load_demo(),
+        _ => load_data(),
     };
-    let decks = Deck::from_string(data);
-    if !is_demo || !is_demo2 {
-        play(decks.clone());
+    let decks = match generated {
+        Some((cards, players)) => generate_decks(cards, players, 0),
+        None => Deck::from_string(data),
+    };
+    let variants: Vec<Box<dyn Rules>> = vec![
+        Box::new(Standard),
+        Box::new(Recursive),
+        Box::new(CustomTrigger{
+            name: "recursive on odd cards".to_string(),
+            trigger: |decks, active, cards| cards.iter().all(|c| c % 2 == 1) && Recursive.sub_game(decks, active, cards),
+        }),
+    ];
+    for rules in variants.iter().filter(|r| variant.is_none_or(|v| r.name() == v)) {
+        if is_demo && is_demo2 && !rules.repeat_guard() {
+            continue;
+        }
+        let log = play(rules.as_ref(), decks.clone(), verbose, record);
+        if record {
+            if let Err(msg) = log.replay(&verbose) {
+                println!("Replay failed: {}", msg);
+            }
+            let path = format!("./{}.json", rules.name().replace(" ", "_"));
+            std::fs::write(&path, serde_json::to_string_pretty(&log.to_json()).unwrap()).expect("Could not write game log");
+        }
     }
-    play_recursive(decks[0].clone(), decks[1].clone(), 1, &verbose);
 }
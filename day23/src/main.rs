@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::Instant;
+
 fn load_demo() -> String {
     "389125467".to_string()
 }
@@ -6,82 +11,229 @@ fn load_data() -> String {
     "586439172".to_string()
 }
 
+// Node index type used for ring links; `u32` halves the footprint of a million-cup ring
+trait Link: Copy + Eq + Hash + std::fmt::Debug {
+    const MAX: usize;
+    fn from_index(idx: usize) -> Self;
+    fn index(self) -> usize;
+}
+
+impl Link for u32 {
+    const MAX: usize = u32::MAX as usize;
+    fn from_index(idx: usize) -> u32 { idx as u32 }
+    fn index(self) -> usize { self as usize }
+}
+
+impl Link for usize {
+    const MAX: usize = usize::MAX;
+    fn from_index(idx: usize) -> usize { idx }
+    fn index(self) -> usize { self }
+}
+
+// Singly linked ring over nodes 0..len, every node pointing to the one after it
+#[derive(Debug, Clone, PartialEq)]
+struct Ring<T: Link = u32> {
+    next: Vec<T>,
+}
+
+impl<T: Link> Ring<T> {
+    fn from_order(order: &[usize]) -> Ring<T> {
+        if order.len() > T::MAX {
+            panic!("Ring of {} nodes does not fit its link type", order.len());
+        }
+        let mut next: Vec<T> = vec![T::from_index(0); order.len()];
+        for idx in 0..order.len() {
+            next[order[idx]] = T::from_index(order[(idx + 1) % order.len()]);
+        }
+        Ring{next}
+    }
+
+    fn len(&self) -> usize {
+        self.next.len()
+    }
+
+    fn next(&self, node: usize) -> usize {
+        self.next[node].index()
+    }
+
+    // Unlinks the `count` nodes following `after`, collecting them in order into `removed`
+    fn remove_after(&mut self, after: usize, count: usize, removed: &mut Vec<usize>) {
+        removed.clear();
+        let mut last = after;
+        for _ in 0..count {
+            last = self.next(last);
+            removed.push(last);
+        }
+        self.next[after] = self.next[last];
+    }
+
+    // Links a chain of nodes, as returned by `remove_after`, back in following `after`
+    fn insert_after(&mut self, after: usize, nodes: &[usize]) {
+        if let (Some(first), Some(last)) = (nodes.first(), nodes.last()) {
+            self.next[*last] = self.next[after];
+            self.next[after] = T::from_index(*first);
+        }
+    }
+
+    fn iter_from(&self, node: usize) -> RingIter<'_, T> {
+        RingIter{ring: self, start: node, node: Some(node)}
+    }
+}
+
+struct RingIter<'a, T: Link> {
+    ring: &'a Ring<T>,
+    start: usize,
+    node: Option<usize>,
+}
+
+impl<'a, T: Link> Iterator for RingIter<'a, T> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let node = self.node?;
+        let next = self.ring.next(node);
+        self.node = match next == self.start { true => None, false => Some(next) };
+        Some(node)
+    }
+}
+
+const MAX_STATE_HASHES: usize = 1 << 22;
+
+fn parse_labels(data: &str) -> Vec<usize> {
+    match data.contains(|c: char| c == ',' || c.is_whitespace()) {
+        true => data
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|l| !l.is_empty())
+            .map(|l| l.parse().expect("Bad cup label"))
+            .collect(),
+        false => data
+            .trim()
+            .chars()
+            .map(|c| c.to_digit(10).expect("Bad cup label") as usize)
+            .collect(),
+    }
+}
+
+// Cups are ring nodes ranked by label, so the destination is always the node before
 #[derive(Debug)]
-struct Game {
-    cups: Vec<usize>,
+struct Game<T: Link = u32> {
+    ring: Ring<T>,
+    labels: Vec<usize>,
     move_id: usize,
-    verbose: bool,    
+    verbose: bool,
     focus: usize,
-    max_cup: usize,
+    pick_up: usize,
+    picked: Vec<usize>,
 }
 
-impl Game {    
-    fn from_string(data: String, verbose: bool) -> Game {
-        let mut cups: Vec<usize> = (0..data.len()+1).into_iter().collect();
-        let chars: Vec<char> = data.chars().collect();
-        let first: usize = chars[0].to_string().parse().unwrap();
-        let mut previous = first.clone();
-        for c in chars[1..].iter() {
-            let v = c.to_string().parse().unwrap();
-            cups[previous] = v;
-            previous = v;
+impl<T: Link> Game<T> {
+    fn from_string(data: String, total: usize, pick_up: usize, verbose: bool) -> Game<T> {
+        let mut order_labels = parse_labels(&data);
+        let max_label = order_labels.iter().max().cloned().unwrap_or(0);
+        let extra = total.saturating_sub(order_labels.len());
+        order_labels.extend(max_label + 1..max_label + 1 + extra);
+        let mut labels = order_labels.clone();
+        labels.sort();
+        labels.dedup();
+        if labels.len() != order_labels.len() {
+            panic!("Cup labels must be unique");
         }
-        cups[previous] = first;
-        let max_cup = cups.len() - 1;
-        Game{cups, move_id: 0, verbose, focus: first, max_cup}
-    }
-
-    fn add_part_two_cups(&mut self) {
-        let start_target = self.max_cup+2;
-        let end = 1000000 - self.cups.len() + start_target + 1;
-        self.cups.extend(start_target..end);
-        let remap = self.cups.iter().position(|c| *c == self.focus).unwrap();
-        self.cups[remap] = self.max_cup + 1;         
-        self.max_cup = 1000000;
-        self.cups[self.max_cup] = self.focus;
+        if pick_up + 2 > labels.len() {
+            panic!("Can't pick up {} of {} cups", pick_up, labels.len());
+        }
+        let order: Vec<usize> = order_labels
+            .iter()
+            .map(|l| labels.binary_search(l).unwrap())
+            .collect();
+        Game{ring: Ring::from_order(&order), labels, move_id: 0, verbose, focus: order[0], pick_up, picked: Vec::with_capacity(pick_up)}
     }
 
     fn print_cups(&self) {
-        let mut next = self.focus;
-        print!("cups: ({})", next);
-        loop {
-            next = self.cups[next];
-            if next == self.focus {
-                break;
-            } else {
-                print!(" {}", next);
-            }
-        }
-        println!();
+        let cups: Vec<String> = self.ring
+            .iter_from(self.focus)
+            .map(|node| match node == self.focus {
+                true => format!("({})", self.labels[node]),
+                false => self.labels[node].to_string(),
+            })
+            .collect();
+        println!("cups: {}", cups.join(" "));
     }
 
     fn do_move(&mut self) {
         self.move_id += 1;
-        let p1 = self.cups[self.focus];
-        let p2 = self.cups[p1];
-        let p3 = self.cups[p2];
-        let cont = self.cups[p3];
-        let mut destination: usize  = match self.focus == 1 {
-            true => self.max_cup,
-            false => self.focus - 1,
-        };
-        while destination == p1 || destination == p2 || destination == p3 {
-            destination = match destination == 1 {
-                true => self.max_cup,
-                false => destination - 1,
-            };
-        }
         if self.verbose {
             println!("-- move {} --", self.move_id);
             self.print_cups();
-            println!("pick up: {} {} {}", p1, p2, p3);
-            println!("destination: {}\n", destination);
         }
-        self.cups[self.focus] = cont;
-        let cont2 = self.cups[destination];
-        self.cups[destination] = p1;
-        self.cups[p3] = cont2;
-        self.focus = self.cups[self.focus];
+        let mut picked = std::mem::take(&mut self.picked);
+        self.ring.remove_after(self.focus, self.pick_up, &mut picked);
+        let size = self.ring.len();
+        let mut destination = (self.focus + size - 1) % size;
+        while picked.contains(&destination) {
+            destination = (destination + size - 1) % size;
+        }
+        if self.verbose {
+            let picked_labels: Vec<String> = picked.iter().map(|p| self.labels[*p].to_string()).collect();
+            println!("pick up: {}", picked_labels.join(" "));
+            println!("destination: {}\n", self.labels[destination]);
+        }
+        self.ring.insert_after(destination, &picked);
+        self.picked = picked;
+        self.focus = self.ring.next(self.focus);
+    }
+
+    fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.focus.hash(&mut hasher);
+        self.ring.next.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Plays up to `rounds`, hashing the state once per ring length of moves. A repeated
+    // hash is confirmed by replaying the suspected period, after which whole periods are
+    // skipped and only the remainder is played out. Detection is abandoned once
+    // MAX_STATE_HASHES states have been recorded without a repeat.
+    fn play(&mut self, rounds: usize, detect_cycles: bool) {
+        let interval = self.ring.len();
+        let mut seen: HashMap<u64, usize> = HashMap::new();
+        let mut detecting = detect_cycles;
+        while self.move_id < rounds {
+            if detecting && self.move_id.is_multiple_of(interval) {
+                if seen.len() >= MAX_STATE_HASHES {
+                    if self.verbose {
+                        println!("No cycle within {} moves, playing on", self.move_id);
+                    }
+                    detecting = false;
+                    continue;
+                }
+                if let Some(start) = seen.insert(self.state_hash(), self.move_id) {
+                    let period = self.move_id - start;
+                    if self.move_id + period > rounds {
+                        detecting = false;
+                    } else {
+                        if self.confirm_cycle(period) {
+                            let skipped = (rounds - self.move_id) / period * period;
+                            if self.verbose || skipped > 0 {
+                                println!("Cycle of {} moves found at move {}, skipping {} moves", period, start, skipped);
+                            }
+                            self.move_id += skipped;
+                            detecting = false;
+                        }
+                        // Confirming played moves, which may have reached `rounds`
+                        continue;
+                    }
+                }
+            }
+            self.do_move();
+        }
+    }
+
+    fn confirm_cycle(&mut self, period: usize) -> bool {
+        let snapshot = (self.ring.clone(), self.focus);
+        for _ in 0..period {
+            self.do_move();
+        }
+        self.ring == snapshot.0 && self.focus == snapshot.1
     }
 
     fn score(&self) {
@@ -89,44 +241,119 @@ impl Game {
             println!("-- final --");
             self.print_cups();
         }
-        let mut solution = "".to_string();
-        let mut pos = 1;
-        loop {
-            pos = self.cups[pos];
-            if pos == 1 { break; }
-            solution = format!("{}{}", solution, pos);
-        }
-        println!("{}", solution);
+        println!("{}", self.labels_after_first());
+    }
+
+    // Labels following the lowest cup, comma separated once any has more than one digit
+    fn labels_after_first(&self) -> String {
+        let separator = match self.labels.iter().any(|l| *l > 9) { true => ",", false => "" };
+        let solution: Vec<String> = self.ring
+            .iter_from(0)
+            .skip(1)
+            .map(|node| self.labels[node].to_string())
+            .collect();
+        solution.join(separator)
     }
 
     fn score_part2(&self) {
-        let a = self.cups[1];
-        let b = self.cups[a];
+        let a = self.ring.next(0);
+        let b = self.ring.next(a);
+        let (a, b) = (self.labels[a], self.labels[b]);
         println!("Star cups are {} and {}, key is {}", a, b, a*b);
     }
 }
 
+fn benchmark(data: &str, total: usize, rounds: usize) {
+    let start = Instant::now();
+    let mut wide: Game<usize> = Game::from_string(data.to_string(), total, 3, false);
+    wide.play(rounds, false);
+    let wide_time = start.elapsed();
+    let start = Instant::now();
+    let mut compact: Game<u32> = Game::from_string(data.to_string(), total, 3, false);
+    compact.play(rounds, false);
+    let compact_time = start.elapsed();
+    let start = Instant::now();
+    let mut cycled: Game<u32> = Game::from_string(data.to_string(), total, 3, false);
+    cycled.play(rounds, true);
+    let cycled_time = start.elapsed();
+    if wide.ring.next.iter().map(|n| n.index()).ne(compact.ring.next.iter().map(|n| n.index()))
+        || compact.ring != cycled.ring {
+        panic!("Benchmark games disagree on the final ring");
+    }
+    println!("{:>8} cups {:>11} moves: usize {:>9.3?} ({} kB), u32 {:>9.3?} ({} kB), u32 with cycles {:>9.3?}",
+        wide.ring.len(), rounds,
+        wide_time, wide.ring.len() * std::mem::size_of::<usize>() / 1024,
+        compact_time, compact.ring.len() * std::mem::size_of::<u32>() / 1024,
+        cycled_time);
+}
+
 fn main() {
     let part_two = true;
     let is_intro = false;
     let is_demo = false;
-    let data = match is_demo { true => load_demo(), false => load_data() };
-    let mut game = Game::from_string(data, !part_two);
-    let rounds = match (is_intro, part_two) { 
+    let bench = false;
+    let mut data = match is_demo { true => load_demo(), false => load_data() };
+    if bench {
+        for (total, rounds) in [(0, 10000000), (20, 10000000), (1000, 10000000), (1000000, 10000000)].iter() {
+            benchmark(&data, *total, *rounds);
+        }
+        return;
+    }
+    let mut rounds = match (is_intro, part_two) {
         (false, true) => 10000000,
         (false, false) => 100,
         _ => 10,
     };
-    if part_two {
-        game.add_part_two_cups();
-    }
-    println!("Game has {} cups", game.cups.len() - 1);
-    while game.move_id < rounds {
-        game.do_move();
-    } 
-    if part_two {
+    let mut total = match part_two { true => 1000000, false => 0 };
+    let mut pick_up = 3;
+    let mut detect_cycles = true;
+    // Overrides: --cups 3,8,9,1,2,5,4,6,7 --moves 100 --total 20 --pick 3 --cycles off
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    for pair in args.chunks(2) {
+        let value = pair.get(1).unwrap_or_else(|| panic!("Missing value for {}", pair[0]));
+        match pair[0].as_str() {
+            "--cups" => data = value.clone(),
+            "--moves" => rounds = value.parse().expect("Bad move count"),
+            "--total" => total = value.parse().expect("Bad cup count"),
+            "--pick" => pick_up = value.parse().expect("Bad pick-up size"),
+            "--cycles" => detect_cycles = value != "off",
+            other => panic!("Unknown argument {}", other),
+        }
+    }
+    let mut game: Game = Game::from_string(data, total, pick_up, !part_two && rounds <= 100);
+    println!("Game has {} cups", game.ring.len());
+    game.play(rounds, detect_cycles);
+    if game.ring.len() > 100 {
         game.score_part2();
     } else {
-        game.score();   
+        game.score();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn played(rounds: usize, detect_cycles: bool) -> Game {
+        let mut game: Game = Game::from_string("389125467".to_string(), 0, 3, false);
+        game.play(rounds, detect_cycles);
+        game
+    }
+
+    #[test]
+    fn cycle_skip_stops_at_rounds() {
+        for rounds in [10, 100, 5112, 7290, 9468, 9469] {
+            let (cycled, plain) = (played(rounds, true), played(rounds, false));
+            assert_eq!(cycled.move_id, rounds);
+            assert!(cycled.ring == plain.ring && cycled.focus == plain.focus, "{} moves", rounds);
+        }
+    }
+
+    #[test]
+    fn multi_digit_labels_are_separated() {
+        assert_eq!(played(100, false).labels_after_first(), "67384529");
+        let mut game: Game = Game::from_string("3,8,9,10,1,2,5,4,6,7".to_string(), 0, 3, false);
+        game.play(10, false);
+        assert_eq!(game.labels_after_first().split(',').count(), 9);
     }
 }